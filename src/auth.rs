@@ -0,0 +1,367 @@
+//! Authentication against AIStore clusters with AuthN enabled
+
+use std::time::Instant;
+
+use base64::Engine;
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+
+use crate::backend;
+use crate::error::AiStoreError;
+use crate::request::RequestPolicy;
+
+/// Refresh tokens this long before they expire
+const REFRESH_MARGIN: Duration = Duration::seconds(60);
+
+/// Source of the JWT sent in the `Authorization` header
+#[async_trait::async_trait]
+pub(crate) trait TokenProvider: std::fmt::Debug + Send + Sync {
+    /// Return a token that is valid for the next request
+    async fn token(&self) -> Result<String, AiStoreError>;
+
+    /// Drop `token` after the cluster rejected it, so the next call to
+    /// [`TokenProvider::token`] fetches a fresh one
+    async fn invalidate(&self, _token: &str) {}
+}
+
+/// A fixed token, e.g. one set through `with_auth_jwt_token`
+#[derive(Debug)]
+pub(crate) struct StaticTokenProvider {
+    token: String,
+}
+
+impl StaticTokenProvider {
+    pub(crate) fn new(token: String) -> Self {
+        Self { token }
+    }
+}
+
+#[async_trait::async_trait]
+impl TokenProvider for StaticTokenProvider {
+    async fn token(&self) -> Result<String, AiStoreError> {
+        Ok(self.token.clone())
+    }
+}
+
+#[derive(Debug, Clone)]
+struct CachedToken {
+    token: String,
+    expires_at: Option<DateTime<Utc>>,
+}
+
+impl CachedToken {
+    fn is_fresh(&self) -> bool {
+        self.expires_at
+            .is_none_or(|expires_at| Utc::now() + REFRESH_MARGIN < expires_at)
+    }
+}
+
+#[derive(Serialize)]
+struct LoginRequest<'a> {
+    password: &'a str,
+}
+
+#[derive(Deserialize)]
+struct LoginResponse {
+    token: String,
+}
+
+#[derive(Deserialize)]
+struct Claims {
+    exp: Option<i64>,
+}
+
+/// Logs in to an AIS AuthN server with a user name and password
+///
+/// The token is cached and a new one is requested shortly before the cached
+/// one expires (according to its `exp` claim) or after the cluster rejects it.
+/// Logins that fail transiently are retried like any other request.
+pub(crate) struct AuthnTokenProvider {
+    client: reqwest::Client,
+    url: String,
+    username: String,
+    password: String,
+    policy: RequestPolicy,
+    cached: Mutex<Option<CachedToken>>,
+}
+
+impl AuthnTokenProvider {
+    pub(crate) fn new(
+        client: reqwest::Client,
+        url: String,
+        username: String,
+        password: String,
+        policy: RequestPolicy,
+    ) -> Self {
+        Self {
+            client,
+            url,
+            username,
+            password,
+            policy,
+            cached: Mutex::new(None),
+        }
+    }
+
+    /// Log in, retrying transient failures with the backoff of the request policy
+    async fn login(&self) -> Result<CachedToken, AiStoreError> {
+        let started = Instant::now();
+        let mut retries = 0;
+        let mut retry_delay = self.policy.initial_retry_delay;
+
+        loop {
+            let error = match self.login_once().await {
+                Ok(token) => return Ok(token),
+                Err(e) => e,
+            };
+
+            let delay = self.policy.jittered(retry_delay);
+            if !is_transient(&error)
+                || retries >= self.policy.max_retries
+                || !self.policy.within_budget(started, delay)
+            {
+                return Err(error);
+            }
+
+            retries += 1;
+            tokio::time::sleep(delay).await;
+            retry_delay = self.policy.next_retry_delay(retry_delay);
+        }
+    }
+
+    async fn login_once(&self) -> Result<CachedToken, AiStoreError> {
+        let url = format!(
+            "{}/v1/users/{}",
+            self.url.trim_end_matches('/'),
+            backend::encode_query(&self.username)
+        );
+
+        let response = self
+            .client
+            .post(url.as_str())
+            .json(&LoginRequest {
+                password: &self.password,
+            })
+            .send()
+            .await
+            .map_err(|e| AiStoreError::Request { source: e })?;
+
+        if !response.status().is_success() {
+            return Err(backend::handle_error_response(&url, response).await);
+        }
+
+        let body = response
+            .text()
+            .await
+            .map_err(|e| AiStoreError::Request { source: e })?;
+
+        let login: LoginResponse =
+            serde_json::from_str(&body).map_err(|e| AiStoreError::InvalidResponse {
+                message: format!("Failed to parse AuthN login response: {}", e),
+            })?;
+
+        let expires_at = token_expiry(&login.token);
+
+        Ok(CachedToken {
+            token: login.token,
+            expires_at,
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl TokenProvider for AuthnTokenProvider {
+    async fn token(&self) -> Result<String, AiStoreError> {
+        let mut cached = self.cached.lock().await;
+
+        if let Some(token) = cached.as_ref().filter(|t| t.is_fresh()) {
+            return Ok(token.token.clone());
+        }
+
+        let token = self.login().await?;
+        *cached = Some(token.clone());
+
+        Ok(token.token)
+    }
+
+    async fn invalidate(&self, token: &str) {
+        let mut cached = self.cached.lock().await;
+
+        if cached.as_ref().is_some_and(|t| t.token == token) {
+            *cached = None;
+        }
+    }
+}
+
+impl std::fmt::Debug for AuthnTokenProvider {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AuthnTokenProvider")
+            .field("url", &self.url)
+            .field("username", &self.username)
+            .finish()
+    }
+}
+
+/// Whether a failed login may succeed if sent again
+///
+/// Logging in twice only hands out two tokens, so any transient failure counts.
+fn is_transient(error: &AiStoreError) -> bool {
+    match error {
+        AiStoreError::Request { source } => {
+            source.is_timeout() || source.is_connect() || source.is_request()
+        }
+        AiStoreError::Http { retryable, .. } => *retryable,
+        _ => false,
+    }
+}
+
+/// Read the `exp` claim of a JWT without validating its signature
+fn token_expiry(token: &str) -> Option<DateTime<Utc>> {
+    let payload = token.split('.').nth(1)?;
+    let payload = base64::engine::general_purpose::URL_SAFE_NO_PAD
+        .decode(payload.trim_end_matches('='))
+        .ok()?;
+    let claims: Claims = serde_json::from_slice(&payload).ok()?;
+
+    DateTime::from_timestamp(claims.exp?, 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::mock::{MockServer, Reply};
+    use crate::request::{ClientExt, HttpClient};
+
+    /// An unsigned JWT with `claims` as its payload
+    fn jwt(claims: &str) -> String {
+        let payload = base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(claims);
+        format!("eyJhbGciOiJub25lIn0.{payload}.sig")
+    }
+
+    /// An AuthN server that hands out `token(n)` on the n-th login
+    async fn authn(token: impl Fn(usize) -> String + Send + Sync + 'static) -> MockServer {
+        let logins = AtomicUsize::new(0);
+        let mut server = MockServer::bind().await;
+        server.serve(move |request| {
+            assert_eq!(request.target, "/v1/users/alice");
+            let n = logins.fetch_add(1, Ordering::SeqCst) + 1;
+            Reply::status(200).body(format!(r#"{{"token": "{}"}}"#, token(n)))
+        });
+        server
+    }
+
+    fn login_provider(server: &MockServer) -> AuthnTokenProvider {
+        AuthnTokenProvider::new(
+            reqwest::Client::new(),
+            server.url().to_string(),
+            "alice".to_string(),
+            "secret".to_string(),
+            RequestPolicy::default(),
+        )
+    }
+
+    #[test]
+    fn token_expiry_reads_the_exp_claim() {
+        assert_eq!(
+            token_expiry(&jwt(r#"{"sub":"alice","exp":1700000000}"#)),
+            DateTime::from_timestamp(1_700_000_000, 0)
+        );
+        assert_eq!(token_expiry(&jwt(r#"{"sub":"alice"}"#)), None);
+        assert_eq!(token_expiry("not-a-jwt"), None);
+        assert_eq!(token_expiry("a.!!!.c"), None);
+    }
+
+    #[tokio::test]
+    async fn tokens_are_cached_until_shortly_before_they_expire() {
+        let expires = Utc::now() + Duration::hours(1);
+        let server =
+            authn(move |n| jwt(&format!(r#"{{"n":{n},"exp":{}}}"#, expires.timestamp()))).await;
+        let provider = login_provider(&server);
+
+        let token = provider.token().await.unwrap();
+        assert_eq!(provider.token().await.unwrap(), token);
+        assert_eq!(server.requests().len(), 1);
+
+        // Within the refresh margin of its expiry, a token is replaced
+        let expires = Utc::now() + Duration::seconds(30);
+        let server =
+            authn(move |n| jwt(&format!(r#"{{"n":{n},"exp":{}}}"#, expires.timestamp()))).await;
+        let provider = login_provider(&server);
+
+        let token = provider.token().await.unwrap();
+        assert_ne!(provider.token().await.unwrap(), token);
+        assert_eq!(server.requests().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn rejected_tokens_are_replaced_once() {
+        let authn = authn(|n| format!("token-{n}")).await;
+
+        let mut cluster = MockServer::bind().await;
+        cluster.serve(|request| match request.header("authorization") {
+            Some("Bearer token-1") => Reply::status(401),
+            _ => Reply::status(200),
+        });
+
+        let client = HttpClient::new(
+            reqwest::Client::new(),
+            Some(Arc::new(login_provider(&authn))),
+            RequestPolicy::default(),
+            None,
+        );
+
+        let response = client
+            .get_with_retry(format!("{}/v1/objects/bucket/key", cluster.url()))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+
+        assert_eq!(authn.requests().len(), 2);
+        let requests = cluster.requests();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[1].header("authorization"), Some("Bearer token-2"));
+    }
+
+    #[tokio::test]
+    async fn failed_logins_are_retried() {
+        let logins = AtomicUsize::new(0);
+        let mut server = MockServer::bind().await;
+        server.serve(move |_| match logins.fetch_add(1, Ordering::SeqCst) {
+            0 => Reply::status(503),
+            _ => Reply::status(200).body(r#"{"token": "token-1"}"#),
+        });
+
+        assert_eq!(login_provider(&server).token().await.unwrap(), "token-1");
+        assert_eq!(server.requests().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn rejected_logins_are_not_retried() {
+        let mut server = MockServer::bind().await;
+        server.serve(|_| Reply::status(401));
+
+        login_provider(&server).token().await.unwrap_err();
+        assert_eq!(server.requests().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn user_names_are_percent_encoded() {
+        let mut server = MockServer::bind().await;
+        server.serve(|_| Reply::status(200).body(r#"{"token": "token-1"}"#));
+        let provider = AuthnTokenProvider::new(
+            reqwest::Client::new(),
+            server.url().to_string(),
+            "a/b c?".to_string(),
+            "secret".to_string(),
+            RequestPolicy::default(),
+        );
+
+        provider.token().await.unwrap();
+        assert_eq!(server.requests()[0].target, "/v1/users/a%2Fb%20c%3F");
+    }
+}
@@ -0,0 +1,972 @@
+//! Internal abstraction over the AIStore HTTP APIs
+//!
+//! [`AiStore`](crate::AiStore) talks to the cluster either through the S3
+//! compatibility layer ([`S3Client`](crate::client::S3Client)) or through the
+//! native REST API ([`NativeClient`](crate::native::NativeClient)). Both
+//! implement [`Backend`], and the helpers in this module hold the response
+//! handling they have in common.
+
+use std::ops::Range;
+use std::sync::Arc;
+use std::time::Instant;
+
+use bytes::Bytes;
+use chrono::{DateTime, TimeZone, Utc};
+use futures::stream::BoxStream;
+use futures::StreamExt;
+use object_store::{
+    path::Path, Attribute, Attributes, GetOptions, GetRange, GetResult, GetResultPayload,
+    ObjectMeta, PutMode, PutOptions, PutPayload, PutResult,
+};
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use reqwest::{Response, StatusCode};
+
+use crate::bucket::Bucket;
+use crate::checksum::{self, ChecksumAlgorithm};
+use crate::error::{AiStoreError, ErrorDetails};
+use crate::request::HttpRequestBuilder;
+use crate::{json, xml};
+
+/// Prefix of the headers that carry user metadata in the S3 API
+const S3_METADATA_PREFIX: &str = "x-amz-meta-";
+
+/// Header carrying user metadata in the native API, as `key=value` pairs separated by `,`
+const AIS_CUSTOM_MD: &str = "ais-custom-md";
+
+/// Characters escaped in object keys placed in a URL path; `/` separates the key's parts
+const KEY_ENCODE_SET: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~')
+    .remove(b'/');
+
+/// Characters escaped in query values and single path segments built into a URL by hand
+const QUERY_ENCODE_SET: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+
+/// How an API flavor transports [`Attribute::Metadata`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum MetadataHeaders {
+    /// One `x-amz-meta-{key}` header per entry
+    S3,
+    /// A single `ais-custom-md` header
+    Native,
+}
+
+/// A single page of a bucket listing
+#[derive(Debug, Default)]
+pub(crate) struct ListPage {
+    pub objects: Vec<ObjectMeta>,
+    pub common_prefixes: Vec<String>,
+    pub next_continuation_token: Option<String>,
+}
+
+/// Operations an AIStore API flavor has to provide
+#[async_trait::async_trait]
+pub(crate) trait Backend: std::fmt::Debug + Send + Sync {
+    async fn put_object(
+        &self,
+        path: &Path,
+        payload: PutPayload,
+        opts: PutOptions,
+    ) -> Result<PutResult, AiStoreError>;
+
+    async fn get_object(&self, path: &Path, options: GetOptions)
+        -> Result<GetResult, AiStoreError>;
+
+    async fn head_object(&self, path: &Path) -> Result<ObjectMeta, AiStoreError>;
+
+    async fn delete_object(&self, path: &Path) -> Result<(), AiStoreError>;
+
+    /// Delete one version of an object in a versioned bucket
+    async fn delete_object_version(&self, path: &Path, version: &str) -> Result<(), AiStoreError>;
+
+    async fn list_objects(
+        &self,
+        prefix: Option<&str>,
+        continuation_token: Option<&str>,
+        max_keys: Option<u32>,
+        delimiter: Option<&str>,
+        start_after: Option<&str>,
+    ) -> Result<ListPage, AiStoreError>;
+
+    /// Delete a batch of objects, reporting the outcome for each path in order
+    async fn delete_objects(
+        &self,
+        paths: Vec<Path>,
+    ) -> Result<Vec<Result<Path, AiStoreError>>, AiStoreError>;
+
+    /// Copy an object within the bucket
+    ///
+    /// With `if_not_exists` the copy is conditional on the server and fails
+    /// with [`AiStoreError::AlreadyExists`] if `to` exists.
+    async fn copy_object(
+        &self,
+        from: &Path,
+        to: &Path,
+        if_not_exists: bool,
+    ) -> Result<(), AiStoreError>;
+
+    /// Copy an object of this bucket into another bucket of the same cluster
+    async fn copy_object_to(
+        &self,
+        from: &Path,
+        dest: &Bucket,
+        to: &Path,
+    ) -> Result<(), AiStoreError>;
+
+    /// Rename an object on the server, without moving its data
+    ///
+    /// Backends that cannot do this return [`AiStoreError::NotSupported`] and
+    /// callers fall back to copy and delete.
+    async fn rename_object(&self, from: &Path, _to: &Path) -> Result<(), AiStoreError> {
+        Err(AiStoreError::NotSupported {
+            message: format!("Server-side rename of {}", from),
+        })
+    }
+
+    async fn initiate_multipart_upload(
+        &self,
+        path: &Path,
+        attributes: &Attributes,
+    ) -> Result<String, AiStoreError>;
+
+    async fn upload_part(
+        &self,
+        path: &Path,
+        upload_id: &str,
+        part_number: u32,
+        data: PutPayload,
+    ) -> Result<String, AiStoreError>;
+
+    async fn complete_multipart_upload(
+        &self,
+        path: &Path,
+        upload_id: &str,
+        parts: Vec<(u32, String)>,
+    ) -> Result<PutResult, AiStoreError>;
+
+    async fn abort_multipart_upload(
+        &self,
+        path: &Path,
+        upload_id: &str,
+    ) -> Result<(), AiStoreError>;
+}
+
+/// Add the range and conditional headers of `options` to a GET/HEAD request
+pub(crate) fn apply_get_options(
+    mut request: HttpRequestBuilder,
+    options: &GetOptions,
+) -> HttpRequestBuilder {
+    if let Some(range) = &options.range {
+        let range_header = match range {
+            GetRange::Bounded(r) => format!("bytes={}-{}", r.start, r.end.saturating_sub(1)),
+            GetRange::Offset(offset) => format!("bytes={}-", offset),
+            GetRange::Suffix(length) => format!("bytes=-{}", length),
+        };
+        request = request.header(reqwest::header::RANGE.to_string(), range_header);
+    }
+
+    if let Some(if_match) = &options.if_match {
+        request = request.header(
+            reqwest::header::IF_MATCH.to_string(),
+            if_match.as_ref() as &str,
+        );
+    }
+
+    if let Some(if_none_match) = &options.if_none_match {
+        request = request.header(
+            reqwest::header::IF_NONE_MATCH.to_string(),
+            if_none_match.as_ref() as &str,
+        );
+    }
+
+    if let Some(if_modified_since) = &options.if_modified_since {
+        request = request.header(
+            reqwest::header::IF_MODIFIED_SINCE.to_string(),
+            if_modified_since
+                .format("%a, %d %b %Y %H:%M:%S GMT")
+                .to_string(),
+        );
+    }
+
+    if let Some(if_unmodified_since) = &options.if_unmodified_since {
+        request = request.header(
+            reqwest::header::IF_UNMODIFIED_SINCE.to_string(),
+            if_unmodified_since
+                .format("%a, %d %b %Y %H:%M:%S GMT")
+                .to_string(),
+        );
+    }
+
+    request
+}
+
+/// Add the conditional headers that implement `mode` to a PUT request
+///
+/// [`PutMode::Create`] becomes `If-None-Match: *`, [`PutMode::Update`] an
+/// `If-Match` on the expected etag. `If-Match` compares etags only, so an
+/// update that names just a version is rejected.
+///
+/// Conditional requests are not idempotent: if the first attempt succeeded but
+/// its response was lost, a retry would fail the condition against the
+/// request's own write. They are only retried when they cannot have arrived.
+pub(crate) fn apply_put_mode(
+    request: HttpRequestBuilder,
+    mode: &PutMode,
+) -> Result<HttpRequestBuilder, AiStoreError> {
+    match mode {
+        PutMode::Overwrite => Ok(request),
+        PutMode::Create => Ok(request
+            .idempotent(false)
+            .header(reqwest::header::IF_NONE_MATCH.to_string(), "*")),
+        PutMode::Update(version) => {
+            let expected = version
+                .e_tag
+                .as_ref()
+                .ok_or_else(|| AiStoreError::Configuration {
+                    message: "PutMode::Update requires an etag".to_string(),
+                })?;
+            Ok(request
+                .idempotent(false)
+                .header(reqwest::header::IF_MATCH.to_string(), expected.as_str()))
+        }
+    }
+}
+
+/// Percent-encode an object key for use in a URL path
+pub(crate) fn encode_key(path: &Path) -> String {
+    utf8_percent_encode(path.as_ref(), KEY_ENCODE_SET).to_string()
+}
+
+/// Percent-encode a query value, e.g. an upload ID, or a single path segment
+pub(crate) fn encode_query(value: &str) -> String {
+    utf8_percent_encode(value, QUERY_ENCODE_SET).to_string()
+}
+
+/// Add `attributes` to an upload request as headers
+pub(crate) fn apply_attributes(
+    mut request: HttpRequestBuilder,
+    attributes: &Attributes,
+    metadata: MetadataHeaders,
+) -> Result<HttpRequestBuilder, AiStoreError> {
+    let mut custom_md = vec![];
+
+    for (attribute, value) in attributes {
+        let value = value.as_ref();
+        request = match attribute {
+            Attribute::ContentType => {
+                request.header(reqwest::header::CONTENT_TYPE.to_string(), value)
+            }
+            Attribute::ContentEncoding => {
+                request.header(reqwest::header::CONTENT_ENCODING.to_string(), value)
+            }
+            Attribute::ContentDisposition => {
+                request.header(reqwest::header::CONTENT_DISPOSITION.to_string(), value)
+            }
+            Attribute::ContentLanguage => {
+                request.header(reqwest::header::CONTENT_LANGUAGE.to_string(), value)
+            }
+            Attribute::CacheControl => {
+                request.header(reqwest::header::CACHE_CONTROL.to_string(), value)
+            }
+            Attribute::Metadata(key) => match metadata {
+                MetadataHeaders::S3 => request.header(format!("{S3_METADATA_PREFIX}{key}"), value),
+                MetadataHeaders::Native => {
+                    // `ais-custom-md` has no escaping, so these would corrupt
+                    // the list of pairs
+                    if [key.as_ref(), value].iter().any(|s| s.contains([',', '='])) {
+                        return Err(AiStoreError::Configuration {
+                            message: format!("Metadata {key}={value} contains ',' or '='"),
+                        });
+                    }
+                    custom_md.push(format!("{key}={value}"));
+                    request
+                }
+            },
+            other => {
+                return Err(AiStoreError::Configuration {
+                    message: format!("Unsupported attribute: {other:?}"),
+                })
+            }
+        };
+    }
+
+    if !custom_md.is_empty() {
+        request = request.header(AIS_CUSTOM_MD, custom_md.join(","));
+    }
+
+    Ok(request)
+}
+
+/// Read the attributes of an object back from the headers of a GET/HEAD response
+pub(crate) fn extract_attributes(headers: &reqwest::header::HeaderMap) -> Attributes {
+    let mut attributes = Attributes::new();
+
+    for (attribute, header) in [
+        (Attribute::ContentType, reqwest::header::CONTENT_TYPE),
+        (
+            Attribute::ContentEncoding,
+            reqwest::header::CONTENT_ENCODING,
+        ),
+        (
+            Attribute::ContentDisposition,
+            reqwest::header::CONTENT_DISPOSITION,
+        ),
+        (
+            Attribute::ContentLanguage,
+            reqwest::header::CONTENT_LANGUAGE,
+        ),
+        (Attribute::CacheControl, reqwest::header::CACHE_CONTROL),
+    ] {
+        if let Some(value) = headers.get(header).and_then(|v| v.to_str().ok()) {
+            attributes.insert(attribute, value.to_string().into());
+        }
+    }
+
+    for (name, value) in headers {
+        let Some(key) = name.as_str().strip_prefix(S3_METADATA_PREFIX) else {
+            continue;
+        };
+        if let Ok(value) = value.to_str() {
+            attributes.insert(
+                Attribute::Metadata(key.to_string().into()),
+                value.to_string().into(),
+            );
+        }
+    }
+
+    if let Some(custom_md) = headers.get(AIS_CUSTOM_MD).and_then(|v| v.to_str().ok()) {
+        for (key, value) in custom_md.split(',').filter_map(|kv| kv.split_once('=')) {
+            attributes.insert(
+                Attribute::Metadata(key.trim().to_string().into()),
+                value.trim().to_string().into(),
+            );
+        }
+    }
+
+    attributes
+}
+
+/// Map the error response to a conditional PUT
+///
+/// A failed condition (412, or 409 for a concurrent write) means the object
+/// already exists for [`PutMode::Create`] and was changed for [`PutMode::Update`].
+pub(crate) async fn put_error_response(
+    path: &Path,
+    mode: &PutMode,
+    response: Response,
+) -> AiStoreError {
+    let condition_failed = matches!(
+        response.status(),
+        StatusCode::PRECONDITION_FAILED | StatusCode::CONFLICT
+    );
+
+    match mode {
+        PutMode::Create if condition_failed => AiStoreError::AlreadyExists {
+            path: path.to_string(),
+            details: error_details(response).await,
+        },
+        PutMode::Update(_) if condition_failed => {
+            AiStoreError::PreconditionFailed { path: path.clone() }
+        }
+        _ => handle_error_response(path.as_ref(), response).await,
+    }
+}
+
+/// Builds a fresh GET request for an object, used to resume interrupted reads
+pub(crate) type RequestFactory = Arc<dyn Fn() -> HttpRequestBuilder + Send + Sync>;
+
+/// Turn the response to a GET/HEAD request into a [`GetResult`]
+///
+/// `request` rebuilds the GET so that the payload stream can pick up where it
+/// left off when the connection drops. With `checksum`, reads of the whole
+/// object are verified against the checksum the cluster reports.
+pub(crate) async fn get_result(
+    path: &Path,
+    options: &GetOptions,
+    response: Response,
+    request: RequestFactory,
+    checksum: Option<ChecksumAlgorithm>,
+) -> Result<GetResult, AiStoreError> {
+    let status = response.status();
+
+    if status == StatusCode::NOT_MODIFIED {
+        return Err(AiStoreError::NotModified { path: path.clone() });
+    }
+
+    if status == StatusCode::PRECONDITION_FAILED {
+        return Err(AiStoreError::PreconditionFailed { path: path.clone() });
+    }
+
+    if !status.is_success() {
+        return Err(handle_error_response(path.as_ref(), response).await);
+    }
+
+    let mut meta = extract_object_meta(path, &response)?;
+    // Without a length, a chunked response only ends where the object does
+    let length_known = options.range.is_some()
+        || response.headers().contains_key("ais-size")
+        || response
+            .headers()
+            .contains_key(reqwest::header::CONTENT_LENGTH);
+    let attributes = extract_attributes(response.headers());
+
+    let range = match &options.range {
+        Some(expected) => {
+            if status != StatusCode::PARTIAL_CONTENT {
+                return Err(AiStoreError::InvalidResponse {
+                    message: format!(
+                        "Received non-partial response for range request on {}",
+                        path
+                    ),
+                });
+            }
+
+            let (actual, size) =
+                parse_content_range(&response).ok_or_else(|| AiStoreError::InvalidResponse {
+                    message: format!("Missing or invalid Content-Range in response for {}", path),
+                })?;
+
+            // Content-Length is the length of the range, the object size is the total
+            meta.size = size;
+
+            let expected = expected
+                .as_range(size)
+                .map_err(|e| AiStoreError::InvalidResponse {
+                    message: e.to_string(),
+                })?;
+            if actual != expected {
+                return Err(AiStoreError::InvalidResponse {
+                    message: format!("Requested {:?}, got {:?}", expected, actual),
+                });
+            }
+
+            actual
+        }
+        None => 0..meta.size,
+    };
+
+    if options.head {
+        Ok(GetResult {
+            meta,
+            range,
+            attributes,
+            payload: GetResultPayload::Stream(Box::pin(futures::stream::empty())),
+        })
+    } else {
+        let expected = match checksum {
+            Some(algorithm) if options.range.is_none() => {
+                checksum::expected_checksum(path, response.headers(), algorithm)
+            }
+            _ => None,
+        };
+
+        let mut stream = resumable_stream(ResumeState {
+            path: path.clone(),
+            request,
+            e_tag: meta.e_tag.clone(),
+            position: range.start,
+            end: length_known.then_some(range.end),
+            body: response.bytes_stream().boxed(),
+            retries: 0,
+            done: false,
+        });
+
+        if let Some((algorithm, expected)) = expected {
+            stream = checksum::verify_stream(stream, path.clone(), algorithm, expected);
+        }
+
+        Ok(GetResult {
+            meta,
+            range,
+            attributes,
+            payload: GetResultPayload::Stream(Box::pin(stream)),
+        })
+    }
+}
+
+/// Quote an etag for `If-Match`, as [`ObjectMeta::e_tag`] holds it without quotes
+pub(crate) fn quote_etag(e_tag: &str) -> String {
+    format!("\"{e_tag}\"")
+}
+
+/// Extract the etag and version an upload response reports
+pub(crate) fn put_result(response: &Response) -> PutResult {
+    let headers = response.headers();
+
+    let e_tag = headers
+        .get(reqwest::header::ETAG)
+        .and_then(|v| v.to_str().ok())
+        .map(|s| s.trim_matches('"').to_string());
+
+    PutResult {
+        e_tag,
+        version: header_version(headers),
+    }
+}
+
+pub(crate) fn extract_object_meta(
+    path: &Path,
+    response: &Response,
+) -> Result<ObjectMeta, AiStoreError> {
+    let headers = response.headers();
+
+    // Native HEAD responses carry the object size in `ais-size`
+    let size = headers
+        .get("ais-size")
+        .or_else(|| headers.get(reqwest::header::CONTENT_LENGTH))
+        .and_then(|v| v.to_str().ok())
+        .and_then(|s| s.parse::<u64>().ok())
+        .unwrap_or(0);
+
+    let last_modified = headers
+        .get(reqwest::header::LAST_MODIFIED)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|s| DateTime::parse_from_rfc2822(s).ok())
+        .map(|dt| dt.with_timezone(&Utc))
+        .or_else(|| {
+            headers
+                .get("ais-atime")
+                .and_then(|v| v.to_str().ok())
+                .and_then(|s| s.parse::<i64>().ok())
+                .map(|nanos| Utc.timestamp_nanos(nanos))
+        })
+        .unwrap_or_else(Utc::now);
+
+    let e_tag = headers
+        .get(reqwest::header::ETAG)
+        .and_then(|v| v.to_str().ok())
+        .map(|s| s.trim_matches('"').to_string());
+
+    Ok(ObjectMeta {
+        location: path.clone(),
+        last_modified,
+        size,
+        e_tag,
+        version: header_version(headers),
+    })
+}
+
+fn header_version(headers: &reqwest::header::HeaderMap) -> Option<String> {
+    headers
+        .get("x-ais-version")
+        .or_else(|| headers.get("ais-version"))
+        .or_else(|| headers.get("x-amz-version-id"))
+        .and_then(|v| v.to_str().ok())
+        .map(|s| s.to_string())
+}
+
+/// Progress of a GET payload stream, kept to reissue the request after a failure
+struct ResumeState {
+    path: Path,
+    request: RequestFactory,
+    /// Pins the resumed reads to the object that was first returned
+    e_tag: Option<String>,
+    /// Offset of the next byte to deliver
+    position: u64,
+    /// End of the range, unless the response had no length
+    end: Option<u64>,
+    body: BoxStream<'static, reqwest::Result<Bytes>>,
+    /// Consecutive reconnects without receiving any data
+    retries: u32,
+    done: bool,
+}
+
+impl ResumeState {
+    /// Request the rest of the range, unless the object changed in the meantime
+    async fn reconnect(&mut self) -> Result<(), AiStoreError> {
+        let range = match self.end {
+            Some(end) => GetRange::Bounded(self.position..end),
+            None => GetRange::Offset(self.position),
+        };
+        let options = GetOptions {
+            range: Some(range),
+            if_match: self.e_tag.as_deref().map(quote_etag),
+            ..Default::default()
+        };
+
+        let response = apply_get_options((self.request)(), &options).send().await?;
+
+        match response.status() {
+            StatusCode::PARTIAL_CONTENT => {}
+            StatusCode::PRECONDITION_FAILED => {
+                return Err(AiStoreError::PreconditionFailed {
+                    path: self.path.clone(),
+                })
+            }
+            status if status.is_success() => {
+                return Err(AiStoreError::InvalidResponse {
+                    message: format!("Expected 206 when resuming {}, got {}", self.path, status),
+                })
+            }
+            _ => return Err(handle_error_response(self.path.as_ref(), response).await),
+        }
+
+        // A cluster that ignored the range would restart the data mid-stream
+        match parse_content_range(&response) {
+            Some((range, _)) if range.start == self.position => {}
+            other => {
+                return Err(AiStoreError::InvalidResponse {
+                    message: format!(
+                        "Resuming {} at byte {} returned range {:?}",
+                        self.path,
+                        self.position,
+                        other.map(|(range, _)| range)
+                    ),
+                })
+            }
+        }
+
+        self.body = response.bytes_stream().boxed();
+        Ok(())
+    }
+}
+
+/// Stream the body of a GET, reissuing a ranged GET with `If-Match` on the
+/// original etag when the connection fails before the range is complete
+///
+/// Gives up after `max_retries` consecutive failures of the request policy, or
+/// once its `retry_timeout` has passed since the first of them. Objects without
+/// an etag are not resumed, as a changed object could not be told apart.
+fn resumable_stream(state: ResumeState) -> BoxStream<'static, object_store::Result<Bytes>> {
+    let policy = (state.request)().retry_policy().clone();
+
+    futures::stream::unfold(state, move |mut state| {
+        let policy = policy.clone();
+        async move {
+            if state.done {
+                return None;
+            }
+
+            let mut retry_delay = policy.initial_retry_delay;
+            let mut failing_since = None;
+            loop {
+                let error = match state.body.next().await {
+                    Some(Ok(bytes)) => {
+                        state.position += bytes.len() as u64;
+                        state.retries = 0;
+                        return Some((Ok(bytes), state));
+                    }
+                    None => match state.end {
+                        Some(end) if state.position < end => AiStoreError::InvalidResponse {
+                            message: format!(
+                                "Stream of {} ended at byte {} of {}",
+                                state.path, state.position, end
+                            ),
+                        },
+                        _ => return None,
+                    },
+                    Some(Err(e)) => AiStoreError::Request { source: e },
+                };
+
+                let started = *failing_since.get_or_insert_with(Instant::now);
+                let delay = policy.jittered(retry_delay);
+
+                let error = if state.e_tag.is_none()
+                    || state.retries >= policy.max_retries
+                    || !policy.within_budget(started, delay)
+                {
+                    Some(error)
+                } else {
+                    state.retries += 1;
+                    tokio::time::sleep(delay).await;
+                    retry_delay = policy.next_retry_delay(retry_delay);
+                    state.reconnect().await.err()
+                };
+
+                if let Some(error) = error {
+                    state.done = true;
+                    return Some((Err(error.into()), state));
+                }
+            }
+        }
+    })
+    .boxed()
+}
+
+/// Parse `Content-Range: bytes {start}-{end}/{total}` into the range and the object size
+pub(crate) fn parse_content_range(response: &Response) -> Option<(Range<u64>, u64)> {
+    let content_range = response.headers().get(reqwest::header::CONTENT_RANGE)?;
+    let content_range = content_range.to_str().ok()?;
+
+    let parts: Vec<&str> = content_range.split(' ').collect();
+    if parts.len() != 2 || parts[0] != "bytes" {
+        return None;
+    }
+
+    let range_parts: Vec<&str> = parts[1].split('/').collect();
+    if range_parts.is_empty() {
+        return None;
+    }
+
+    let byte_range: Vec<&str> = range_parts[0].split('-').collect();
+    if byte_range.len() != 2 {
+        return None;
+    }
+
+    let start = byte_range[0].parse::<u64>().ok()?;
+    let end = byte_range[1].parse::<u64>().ok()? + 1;
+    let size = range_parts.get(1)?.parse::<u64>().ok()?;
+
+    Some((start..end, size))
+}
+
+/// Turn a failed response into an error about `path`, the object or bucket the
+/// request was for
+pub(crate) async fn handle_error_response(path: &str, response: Response) -> AiStoreError {
+    let status = response.status();
+    let details = error_details(response).await;
+    let path = path.to_string();
+
+    match status {
+        StatusCode::NOT_FOUND => AiStoreError::NotFound { path, details },
+        StatusCode::FORBIDDEN => AiStoreError::Forbidden { path, details },
+        StatusCode::UNAUTHORIZED => AiStoreError::Unauthorized { path, details },
+        StatusCode::CONFLICT => AiStoreError::AlreadyExists { path, details },
+        _ => AiStoreError::Http {
+            status: status.as_u16(),
+            retryable: is_retryable(status, details.code.as_deref()),
+            details,
+        },
+    }
+}
+
+/// Read the error a failed response describes
+async fn error_details(response: Response) -> ErrorDetails {
+    let status = response.status();
+    let header_request_id = response
+        .headers()
+        .get("x-amz-request-id")
+        .and_then(|v| v.to_str().ok())
+        .map(|s| s.to_string());
+    let body = response.text().await.unwrap_or_default();
+
+    let mut details = parse_error_body(&body);
+    details.request_id = details.request_id.or(header_request_id);
+    if details.message.is_empty() {
+        details.message = status.to_string();
+    }
+
+    details
+}
+
+/// Parse an S3 XML `<Error>` or a native JSON error, falling back to the raw text
+fn parse_error_body(body: &str) -> ErrorDetails {
+    if let Ok(error) = xml::from_xml::<xml::ErrorResponse>(body) {
+        return ErrorDetails {
+            message: error.message.unwrap_or_else(|| error.code.clone()),
+            code: Some(error.code),
+            request_id: error.request_id,
+            resource: error.resource,
+        };
+    }
+
+    if let Ok(error) = serde_json::from_str::<json::ErrorResponse>(body) {
+        return ErrorDetails {
+            code: None,
+            message: error.message,
+            request_id: None,
+            resource: error.url_path,
+        };
+    }
+
+    ErrorDetails::new(body)
+}
+
+/// Whether a failed request is worth sending again, from its status and S3 error code
+fn is_retryable(status: StatusCode, code: Option<&str>) -> bool {
+    matches!(
+        code,
+        Some(
+            "SlowDown"
+                | "Throttling"
+                | "ThrottlingException"
+                | "RequestLimitExceeded"
+                | "RequestTimeout"
+                | "InternalError"
+                | "ServiceUnavailable"
+        )
+    ) || matches!(
+        status,
+        StatusCode::REQUEST_TIMEOUT
+            | StatusCode::TOO_MANY_REQUESTS
+            | StatusCode::INTERNAL_SERVER_ERROR
+            | StatusCode::BAD_GATEWAY
+            | StatusCode::SERVICE_UNAVAILABLE
+            | StatusCode::GATEWAY_TIMEOUT
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{MockServer, Reply};
+
+    #[test]
+    fn s3_error_bodies_are_parsed() {
+        let details = parse_error_body(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\
+             <Error><Code>SlowDown</Code><Message>Please reduce your request rate</Message>\
+             <Resource>/bucket/key</Resource><RequestId>req-1</RequestId></Error>",
+        );
+
+        assert_eq!(
+            details,
+            ErrorDetails {
+                code: Some("SlowDown".to_string()),
+                message: "Please reduce your request rate".to_string(),
+                request_id: Some("req-1".to_string()),
+                resource: Some("/bucket/key".to_string()),
+            }
+        );
+
+        // Without a message, the code stands in for it
+        let details = parse_error_body("<Error><Code>NoSuchKey</Code></Error>");
+        assert_eq!(details.message, "NoSuchKey");
+    }
+
+    #[test]
+    fn native_error_bodies_are_parsed() {
+        let details = parse_error_body(
+            r#"{"message": "object does not exist", "status": 404, "url_path": "/v1/objects/bucket/key"}"#,
+        );
+
+        assert_eq!(
+            details,
+            ErrorDetails {
+                code: None,
+                message: "object does not exist".to_string(),
+                request_id: None,
+                resource: Some("/v1/objects/bucket/key".to_string()),
+            }
+        );
+    }
+
+    #[test]
+    fn other_error_bodies_are_kept_as_text() {
+        assert_eq!(
+            parse_error_body("bucket is busy"),
+            ErrorDetails::new("bucket is busy")
+        );
+    }
+
+    #[test]
+    fn retryable_errors() {
+        assert!(is_retryable(StatusCode::BAD_REQUEST, Some("SlowDown")));
+        assert!(is_retryable(StatusCode::SERVICE_UNAVAILABLE, None));
+        assert!(!is_retryable(StatusCode::BAD_REQUEST, None));
+        assert!(!is_retryable(
+            StatusCode::BAD_REQUEST,
+            Some("InvalidArgument")
+        ));
+    }
+
+    /// Map the response of `server` to `target` like a failed request for `path`
+    async fn error_for(server: &MockServer, target: &str) -> AiStoreError {
+        let response = reqwest::get(format!("{}{target}", server.url()))
+            .await
+            .unwrap();
+        handle_error_response("bucket/key", response).await
+    }
+
+    #[tokio::test]
+    async fn error_responses_are_mapped() {
+        let mut server = MockServer::bind().await;
+        server.serve(|request| match request.target.as_str() {
+            "/slow" => Reply::status(503)
+                .header("x-amz-request-id", "req-1")
+                .body("<Error><Code>SlowDown</Code><Message>Slow down</Message></Error>"),
+            "/forbidden" => Reply::status(403),
+            "/unauthorized" => Reply::status(401),
+            _ => Reply::status(400).body("bad"),
+        });
+
+        let err = error_for(&server, "/slow").await;
+        let AiStoreError::Http {
+            status,
+            details,
+            retryable,
+        } = err
+        else {
+            panic!("{err}");
+        };
+        assert_eq!(status, 503);
+        assert!(retryable);
+        assert_eq!(details.code.as_deref(), Some("SlowDown"));
+        // The body has no request ID, the header does
+        assert_eq!(details.request_id.as_deref(), Some("req-1"));
+
+        let err = object_store::Error::from(error_for(&server, "/forbidden").await);
+        assert!(
+            matches!(&err, object_store::Error::PermissionDenied { path, .. } if path == "bucket/key"),
+            "{err}"
+        );
+
+        let err = object_store::Error::from(error_for(&server, "/unauthorized").await);
+        assert!(
+            matches!(&err, object_store::Error::Unauthenticated { path, .. } if path == "bucket/key"),
+            "{err}"
+        );
+
+        let err = error_for(&server, "/bad").await;
+        assert!(
+            matches!(&err, AiStoreError::Http { status: 400, retryable: false, details }
+                if details.message == "bad"),
+            "{err}"
+        );
+    }
+    /// Turn the response of `server` to a GET of `range` into a [`GetResult`]
+    async fn ranged_get(server: &MockServer, range: GetRange) -> Result<GetResult, AiStoreError> {
+        use crate::request::ClientExt;
+
+        let client = crate::request::HttpClient::new(
+            reqwest::Client::new(),
+            None,
+            crate::RequestPolicy::default(),
+            None,
+        );
+        let url = format!("{}/key", server.url());
+        let request: RequestFactory = Arc::new(move || client.get_with_retry(url.clone()));
+        let options = GetOptions {
+            range: Some(range),
+            ..Default::default()
+        };
+
+        let response = apply_get_options(request(), &options).send().await?;
+        get_result(&Path::from("key"), &options, response, request, None).await
+    }
+
+    #[tokio::test]
+    async fn ranged_reads_check_the_content_range() {
+        let mut server = MockServer::bind().await;
+        server.serve(|request| {
+            let content_range = match request.header("range") {
+                Some("bytes=2-5") => "bytes 2-5/100",
+                _ => "bytes 0-3/100",
+            };
+            Reply::status(206)
+                .header("content-range", content_range)
+                .body("abcd")
+        });
+
+        // Content-Length is the length of the range, the size comes from the total
+        let result = ranged_get(&server, GetRange::Bounded(2..6)).await.unwrap();
+        assert_eq!(result.meta.size, 100);
+        assert_eq!(result.range, 2..6);
+        assert_eq!(result.bytes().await.unwrap().as_ref(), b"abcd");
+
+        let err = ranged_get(&server, GetRange::Bounded(4..8))
+            .await
+            .err()
+            .unwrap();
+        assert!(
+            matches!(&err, AiStoreError::InvalidResponse { message } if message.contains("got 0..4")),
+            "{err}"
+        );
+    }
+}
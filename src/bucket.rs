@@ -0,0 +1,135 @@
+use std::str::FromStr;
+
+use crate::BuilderError;
+
+/// Backend provider of an AIStore bucket
+///
+/// Native AIS buckets use [`Provider::Ais`]; the others are cloud buckets
+/// that AIStore fronts as a cache.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum Provider {
+    #[default]
+    Ais,
+    Aws,
+    Gcp,
+    Azure,
+}
+
+impl Provider {
+    /// The value AIStore uses for this provider in the `provider` query parameter
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Provider::Ais => "ais",
+            Provider::Aws => "aws",
+            Provider::Gcp => "gcp",
+            Provider::Azure => "azure",
+        }
+    }
+}
+
+impl std::fmt::Display for Provider {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Provider {
+    type Err = BuilderError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ais" => Ok(Provider::Ais),
+            "aws" | "s3" => Ok(Provider::Aws),
+            "gcp" | "gs" => Ok(Provider::Gcp),
+            "azure" | "az" => Ok(Provider::Azure),
+            _ => Err(BuilderError::InvalidProvider {
+                provider: s.to_string(),
+            }),
+        }
+    }
+}
+
+/// Namespace of a bucket, e.g. a bucket of a remote AIS cluster
+///
+/// Rendered the way AIStore expects it: `@uuid#name`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct Namespace {
+    /// UUID or alias of the remote cluster (empty for the local cluster)
+    pub uuid: String,
+    /// Name of the namespace within the cluster
+    pub name: String,
+}
+
+impl std::fmt::Display for Namespace {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "@{}", self.uuid)?;
+        if !self.name.is_empty() {
+            write!(f, "#{}", self.name)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Namespace {
+    type Err = BuilderError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s
+            .strip_prefix('@')
+            .ok_or_else(|| BuilderError::InvalidNamespace {
+                namespace: s.to_string(),
+            })?;
+
+        let (uuid, name) = s.split_once('#').unwrap_or((s, ""));
+
+        Ok(Namespace {
+            uuid: uuid.to_string(),
+            name: name.to_string(),
+        })
+    }
+}
+
+/// A provider-qualified AIStore bucket
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Bucket {
+    pub name: String,
+    pub provider: Provider,
+    pub namespace: Option<Namespace>,
+}
+
+impl Bucket {
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            provider: Provider::default(),
+            namespace: None,
+        }
+    }
+
+    /// Query parameters that select this bucket in the native API
+    pub(crate) fn query_params(&self) -> Vec<(String, String)> {
+        let mut params = vec![("provider".to_string(), self.provider.to_string())];
+
+        if let Some(namespace) = &self.namespace {
+            params.push(("namespace".to_string(), namespace.to_string()));
+        }
+
+        params
+    }
+}
+
+impl From<&crate::AiStore> for Bucket {
+    fn from(store: &crate::AiStore) -> Self {
+        store.bucket().clone()
+    }
+}
+
+impl std::fmt::Display for Bucket {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}://", self.provider)?;
+        if let Some(namespace) = &self.namespace {
+            write!(f, "{namespace}/")?;
+        }
+        f.write_str(&self.name)
+    }
+}
@@ -1,144 +1,1192 @@
-use std::sync::Arc;
-use std::time::Duration;
-
-use crate::{
-    client::{S3Client, S3Config},
-    AiStore,
-};
-
-#[derive(Default)]
-pub struct AiStoreBuilder {
-    endpoint: Option<String>,
-    bucket_name: Option<String>,
-    auth_jwt_token: Option<String>,
-    allow_http: bool,
-    timeout: Option<Duration>,
-    connect_timeout: Option<Duration>,
-    s3_api_via_root: bool,
-}
-
-impl AiStoreBuilder {
-    pub fn new() -> Self {
-        AiStoreBuilder::default()
-    }
-
-    /// Set the AIStore endpoint URL (e.g., "aistore.example.com" or "localhost:8080")
-    pub fn with_endpoint(mut self, endpoint: impl Into<String>) -> Self {
-        self.endpoint = Some(endpoint.into());
-        self
-    }
-
-    /// Set the bucket name to use
-    pub fn with_bucket_name(mut self, bucket_name: impl Into<String>) -> Self {
-        self.bucket_name = Some(bucket_name.into());
-        self
-    }
-
-    /// Set the JWT authentication token
-    pub fn with_auth_jwt_token(mut self, auth_jwt_token: impl Into<String>) -> Self {
-        self.auth_jwt_token = Some(auth_jwt_token.into());
-        self
-    }
-
-    /// Allow HTTP connections (default: false, HTTPS only)
-    pub fn with_allow_http(mut self, allow_http: bool) -> Self {
-        self.allow_http = allow_http;
-        self
-    }
-
-    /// Set the request timeout
-    pub fn with_timeout(mut self, timeout: Duration) -> Self {
-        self.timeout = Some(timeout);
-        self
-    }
-
-    /// Set the connection timeout
-    pub fn with_connect_timeout(mut self, timeout: Duration) -> Self {
-        self.connect_timeout = Some(timeout);
-        self
-    }
-
-    /// Configure S3 API path routing.
-    ///
-    /// When `true`, the S3 API is served at the root: `http(s)://{endpoint}/{bucket}`
-    /// When `false` (default), the S3 API is served at: `http(s)://{endpoint}/s3/{bucket}`
-    pub fn with_s3_api_via_root(mut self, s3_api_via_root: bool) -> Self {
-        self.s3_api_via_root = s3_api_via_root;
-        self
-    }
-
-    /// Build the AiStore client
-    pub fn build(self) -> object_store::Result<AiStore> {
-        let bucket = self.bucket_name.ok_or(BuilderError::MissingBucketName)?;
-        let endpoint = self.endpoint.ok_or(BuilderError::MissingEndpoint)?;
-
-        let url = if self.s3_api_via_root {
-            format!("{endpoint}/{bucket}")
-        } else {
-            format!("{endpoint}/s3/{bucket}")
-        };
-
-        let mut client_builder = reqwest::Client::builder();
-
-        if let Some(timeout) = self.timeout {
-            client_builder = client_builder.timeout(timeout);
-        }
-
-        if let Some(connect_timeout) = self.connect_timeout {
-            client_builder = client_builder.connect_timeout(connect_timeout);
-        }
-
-        let mut headers = reqwest::header::HeaderMap::new();
-
-        if let Some(jwt) = &self.auth_jwt_token {
-            let auth_value = reqwest::header::HeaderValue::from_str(&format!("Bearer {jwt}"))
-                .map_err(|e| BuilderError::InvalidAuthToken {
-                    message: e.to_string(),
-                })?;
-            headers.insert(reqwest::header::AUTHORIZATION, auth_value);
-        }
-
-        client_builder = client_builder
-            .default_headers(headers)
-            .https_only(!self.allow_http);
-
-        let http_client = client_builder
-            .build()
-            .map_err(|e| BuilderError::HttpClient { source: e })?;
-
-        let client_config = S3Config { url };
-        let client = Arc::new(S3Client::new(client_config, http_client));
-
-        Ok(AiStore {
-            client,
-            bucket_name: bucket,
-        })
-    }
-}
-
-#[derive(Debug, thiserror::Error)]
-pub enum BuilderError {
-    #[error("Missing bucket name")]
-    MissingBucketName,
-
-    #[error("Missing endpoint")]
-    MissingEndpoint,
-
-    #[error("Invalid auth token: {message}")]
-    InvalidAuthToken { message: String },
-
-    #[error("Failed to build HTTP client: {source}")]
-    HttpClient {
-        #[source]
-        source: reqwest::Error,
-    },
-}
-
-impl From<BuilderError> for object_store::Error {
-    fn from(source: BuilderError) -> Self {
-        Self::Generic {
-            store: "aistore",
-            source: Box::new(source),
-        }
-    }
-}
+use std::ffi::OsString;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::{
+    auth::{AuthnTokenProvider, StaticTokenProvider, TokenProvider},
+    backend::Backend,
+    bucket::{Bucket, Namespace, Provider},
+    checksum::ChecksumAlgorithm,
+    client::{S3Client, S3Config},
+    native::{NativeClient, NativeConfig},
+    parse,
+    request::{HttpClient, RedirectRewrite, RequestPolicy},
+    AiStore,
+};
+
+/// Default number of multipart upload parts in flight at once
+const DEFAULT_MULTIPART_CONCURRENCY: usize = 8;
+
+/// Default number of merged range requests `get_ranges` keeps in flight
+const DEFAULT_RANGE_CONCURRENCY: usize = 10;
+
+/// Which AIStore HTTP API the store talks to
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum AiStoreApi {
+    /// The S3 compatibility layer (`/s3/{bucket}`)
+    ///
+    /// S3 requests address buckets by name alone, so only AIS buckets outside
+    /// any namespace can be reached; a cloud or namespaced bucket of the same
+    /// name would resolve to a different bucket.
+    ///
+    /// Listings through this API do not report object versions; only the
+    /// native API does.
+    #[default]
+    S3,
+    /// The native REST API (`/v1/objects/{bucket}/{object}`, `/v1/buckets/{bucket}`)
+    Native,
+}
+
+impl AiStoreApi {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AiStoreApi::S3 => "s3",
+            AiStoreApi::Native => "native",
+        }
+    }
+}
+
+impl FromStr for AiStoreApi {
+    type Err = BuilderError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "s3" => Ok(AiStoreApi::S3),
+            "native" => Ok(AiStoreApi::Native),
+            _ => Err(BuilderError::InvalidApi { api: s.to_string() }),
+        }
+    }
+}
+
+/// How [`AiStore`] implements `copy_if_not_exists` and `rename_if_not_exists`
+///
+/// `rename_if_not_exists` checks the destination with a HEAD before the native
+/// rename in either mode; the mode only applies when it falls back to a copy.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CopyMode {
+    /// A single copy request with `If-None-Match: *`, checked by the server
+    #[default]
+    Conditional,
+    /// A HEAD of the destination followed by an unconditional copy
+    ///
+    /// Two writers can both succeed. Only use this with clusters that reject
+    /// conditional copies.
+    HeadThenCopy,
+}
+
+impl CopyMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CopyMode::Conditional => "conditional",
+            CopyMode::HeadThenCopy => "head-then-copy",
+        }
+    }
+}
+
+impl FromStr for CopyMode {
+    type Err = BuilderError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "conditional" => Ok(CopyMode::Conditional),
+            "head-then-copy" | "head_then_copy" => Ok(CopyMode::HeadThenCopy),
+            _ => Err(BuilderError::InvalidConfigValue {
+                key: AiStoreConfigKey::CopyIfNotExists.as_ref().to_string(),
+                value: s.to_string(),
+            }),
+        }
+    }
+}
+
+/// Configuration keys for [`AiStoreBuilder`]
+///
+/// Keys parse from their canonical name (e.g. `ais_endpoint`) and from the
+/// short aliases listed on each variant, so a store can be configured from a
+/// `HashMap<String, String>`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum AiStoreConfigKey {
+    /// AIStore endpoint URL
+    ///
+    /// Supported keys: `ais_endpoint`, `endpoint`
+    Endpoint,
+
+    /// Bucket name
+    ///
+    /// Supported keys: `ais_bucket`, `ais_bucket_name`, `bucket`, `bucket_name`
+    Bucket,
+
+    /// Bucket provider, see [`Provider`]
+    ///
+    /// Supported keys: `ais_provider`, `provider`
+    Provider,
+
+    /// Bucket namespace in the form `@uuid#name`, see [`Namespace`]
+    ///
+    /// Supported keys: `ais_namespace`, `namespace`
+    Namespace,
+
+    /// API to use, `s3` or `native`
+    ///
+    /// Supported keys: `ais_api`, `api`
+    Api,
+
+    /// Static JWT
+    ///
+    /// Supported keys: `ais_authn_token`, `authn_token`, `token`
+    AuthnToken,
+
+    /// File to read a static JWT from
+    ///
+    /// Supported keys: `ais_authn_token_file`, `authn_token_file`
+    AuthnTokenFile,
+
+    /// URL of the AuthN server
+    ///
+    /// Supported keys: `ais_authn_url`, `authn_url`
+    AuthnUrl,
+
+    /// AuthN user name
+    ///
+    /// Supported keys: `ais_authn_username`, `authn_username`, `username`
+    AuthnUsername,
+
+    /// AuthN password
+    ///
+    /// Supported keys: `ais_authn_password`, `authn_password`, `password`
+    AuthnPassword,
+
+    /// Skip verification of the server's TLS certificate
+    ///
+    /// Supported keys: `ais_skip_verify_crt`, `skip_verify_crt`
+    SkipVerifyCrt,
+
+    /// Allow HTTP connections
+    ///
+    /// Supported keys: `ais_allow_http`, `allow_http`
+    AllowHttp,
+
+    /// Request timeout, e.g. `30s`
+    ///
+    /// Supported keys: `ais_timeout`, `timeout`
+    Timeout,
+
+    /// Connection timeout, e.g. `5s`
+    ///
+    /// Supported keys: `ais_connect_timeout`, `connect_timeout`
+    ConnectTimeout,
+
+    /// Serve the S3 API at the root instead of under `/s3`
+    ///
+    /// Supported keys: `ais_s3_api_via_root`, `s3_api_via_root`
+    S3ApiViaRoot,
+
+    /// Maximum number of parts of a multipart upload in flight at once
+    ///
+    /// Supported keys: `ais_multipart_concurrency`, `multipart_concurrency`
+    MultipartConcurrency,
+
+    /// How to copy without overwriting: `conditional` or `head-then-copy`
+    ///
+    /// Supported keys: `ais_copy_if_not_exists`, `copy_if_not_exists`
+    CopyIfNotExists,
+
+    /// Largest gap in bytes between two ranges that `get_ranges` fetches together
+    ///
+    /// Supported keys: `ais_range_coalesce`, `range_coalesce`
+    RangeCoalesce,
+
+    /// Maximum number of range requests `get_ranges` keeps in flight
+    ///
+    /// Supported keys: `ais_range_concurrency`, `range_concurrency`
+    RangeConcurrency,
+
+    /// Checksum sent with uploads and verified on full reads: `md5`, `crc32c`,
+    /// `sha256` or `xxhash` (native API only)
+    ///
+    /// Supported keys: `ais_checksum`, `checksum`
+    Checksum,
+
+    /// Hosts to replace in redirects, as `from=to` pairs separated by `,`
+    ///
+    /// `from` is `host` or `host:port`; `to` is `host`, `host:port` or
+    /// `scheme://host[:port]`. A bare `host` keeps the port of the redirect.
+    /// IPv6 addresses go in brackets, e.g. `[fd00::1]:8081`.
+    ///
+    /// Supported keys: `ais_redirect_host_map`, `redirect_host_map`
+    RedirectHostMap,
+
+    /// Hosts that keep receiving credentials after a redirect, separated by `,`
+    ///
+    /// See [`RequestPolicy::redirect_auth_hosts`].
+    ///
+    /// Supported keys: `ais_redirect_auth_hosts`, `redirect_auth_hosts`
+    RedirectAuthHosts,
+}
+
+impl AsRef<str> for AiStoreConfigKey {
+    fn as_ref(&self) -> &str {
+        match self {
+            Self::Endpoint => "ais_endpoint",
+            Self::Bucket => "ais_bucket",
+            Self::Provider => "ais_provider",
+            Self::Namespace => "ais_namespace",
+            Self::Api => "ais_api",
+            Self::AuthnToken => "ais_authn_token",
+            Self::AuthnTokenFile => "ais_authn_token_file",
+            Self::AuthnUrl => "ais_authn_url",
+            Self::AuthnUsername => "ais_authn_username",
+            Self::AuthnPassword => "ais_authn_password",
+            Self::SkipVerifyCrt => "ais_skip_verify_crt",
+            Self::AllowHttp => "ais_allow_http",
+            Self::Timeout => "ais_timeout",
+            Self::ConnectTimeout => "ais_connect_timeout",
+            Self::S3ApiViaRoot => "ais_s3_api_via_root",
+            Self::MultipartConcurrency => "ais_multipart_concurrency",
+            Self::CopyIfNotExists => "ais_copy_if_not_exists",
+            Self::RangeCoalesce => "ais_range_coalesce",
+            Self::RangeConcurrency => "ais_range_concurrency",
+            Self::Checksum => "ais_checksum",
+            Self::RedirectHostMap => "ais_redirect_host_map",
+            Self::RedirectAuthHosts => "ais_redirect_auth_hosts",
+        }
+    }
+}
+
+impl FromStr for AiStoreConfigKey {
+    type Err = object_store::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ais_endpoint" | "endpoint" => Ok(Self::Endpoint),
+            "ais_bucket" | "ais_bucket_name" | "bucket" | "bucket_name" => Ok(Self::Bucket),
+            "ais_provider" | "provider" => Ok(Self::Provider),
+            "ais_namespace" | "namespace" => Ok(Self::Namespace),
+            "ais_api" | "api" => Ok(Self::Api),
+            "ais_authn_token" | "authn_token" | "token" => Ok(Self::AuthnToken),
+            "ais_authn_token_file" | "authn_token_file" => Ok(Self::AuthnTokenFile),
+            "ais_authn_url" | "authn_url" => Ok(Self::AuthnUrl),
+            "ais_authn_username" | "authn_username" | "username" => Ok(Self::AuthnUsername),
+            "ais_authn_password" | "authn_password" | "password" => Ok(Self::AuthnPassword),
+            "ais_skip_verify_crt" | "skip_verify_crt" => Ok(Self::SkipVerifyCrt),
+            "ais_allow_http" | "allow_http" => Ok(Self::AllowHttp),
+            "ais_timeout" | "timeout" => Ok(Self::Timeout),
+            "ais_connect_timeout" | "connect_timeout" => Ok(Self::ConnectTimeout),
+            "ais_s3_api_via_root" | "s3_api_via_root" => Ok(Self::S3ApiViaRoot),
+            "ais_multipart_concurrency" | "multipart_concurrency" => Ok(Self::MultipartConcurrency),
+            "ais_copy_if_not_exists" | "copy_if_not_exists" => Ok(Self::CopyIfNotExists),
+            "ais_range_coalesce" | "range_coalesce" => Ok(Self::RangeCoalesce),
+            "ais_range_concurrency" | "range_concurrency" => Ok(Self::RangeConcurrency),
+            "ais_checksum" | "checksum" => Ok(Self::Checksum),
+            "ais_redirect_host_map" | "redirect_host_map" => Ok(Self::RedirectHostMap),
+            "ais_redirect_auth_hosts" | "redirect_auth_hosts" => Ok(Self::RedirectAuthHosts),
+            _ => Err(object_store::Error::UnknownConfigurationKey {
+                store: "aistore",
+                key: s.into(),
+            }),
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct AiStoreBuilder {
+    url: Option<String>,
+    endpoint: Option<String>,
+    bucket_name: Option<String>,
+    provider: Provider,
+    namespace: Option<Namespace>,
+    api: AiStoreApi,
+    auth_jwt_token: Option<String>,
+    auth_jwt_token_file: Option<String>,
+    authn_url: Option<String>,
+    authn_username: Option<String>,
+    authn_password: Option<String>,
+    allow_http: bool,
+    skip_verify_crt: bool,
+    timeout: Option<Duration>,
+    connect_timeout: Option<Duration>,
+    s3_api_via_root: bool,
+    multipart_concurrency: Option<usize>,
+    copy_mode: CopyMode,
+    range_coalesce: Option<u64>,
+    range_concurrency: Option<usize>,
+    checksum: Option<ChecksumAlgorithm>,
+    retry_policy: RequestPolicy,
+    redirect_host_map: Vec<(String, String)>,
+    redirect_rewrite: Option<RedirectRewrite>,
+    redirect_auth_hosts: Vec<String>,
+    /// First value passed to [`AiStoreBuilder::with_config`] that failed to parse
+    invalid_config: Option<(AiStoreConfigKey, String)>,
+}
+
+impl AiStoreBuilder {
+    pub fn new() -> Self {
+        AiStoreBuilder::default()
+    }
+
+    /// Create a builder initialized from the `AIS_*` environment variables
+    ///
+    /// Every variable whose lowercased name is an [`AiStoreConfigKey`] is applied,
+    /// e.g. `AIS_ENDPOINT`, `AIS_AUTHN_TOKEN`, `AIS_AUTHN_TOKEN_FILE` or
+    /// `AIS_SKIP_VERIFY_CRT`.
+    pub fn from_env() -> Self {
+        Self::from_vars(std::env::vars_os())
+    }
+
+    /// Apply the `AIS_*` entries of `vars`, as [`AiStoreBuilder::from_env`] does
+    /// with the environment
+    fn from_vars(vars: impl IntoIterator<Item = (OsString, OsString)>) -> Self {
+        let mut builder = Self::default();
+
+        for (os_key, os_value) in vars {
+            if let (Some(key), Some(value)) = (os_key.to_str(), os_value.to_str()) {
+                if key.starts_with("AIS_") {
+                    if let Ok(config_key) = key.to_ascii_lowercase().parse() {
+                        builder = builder.with_config(config_key, value);
+                    }
+                }
+            }
+        }
+
+        builder
+    }
+
+    /// Set an option from its [`AiStoreConfigKey`] and string value
+    ///
+    /// Values that fail to parse are reported by [`AiStoreBuilder::build`].
+    pub fn with_config(mut self, key: AiStoreConfigKey, value: impl Into<String>) -> Self {
+        let value = value.into();
+
+        let parsed = match key {
+            AiStoreConfigKey::Endpoint => {
+                self.endpoint = Some(value);
+                return self;
+            }
+            AiStoreConfigKey::Bucket => {
+                self.bucket_name = Some(value);
+                return self;
+            }
+            AiStoreConfigKey::AuthnToken => {
+                self.auth_jwt_token = Some(value);
+                return self;
+            }
+            AiStoreConfigKey::AuthnTokenFile => {
+                self.auth_jwt_token_file = Some(value);
+                return self;
+            }
+            AiStoreConfigKey::AuthnUrl => {
+                self.authn_url = Some(value);
+                return self;
+            }
+            AiStoreConfigKey::AuthnUsername => {
+                self.authn_username = Some(value);
+                return self;
+            }
+            AiStoreConfigKey::AuthnPassword => {
+                self.authn_password = Some(value);
+                return self;
+            }
+            AiStoreConfigKey::Provider => value.parse().map(|v| self.provider = v).is_ok(),
+            AiStoreConfigKey::Namespace => value.parse().map(|v| self.namespace = Some(v)).is_ok(),
+            AiStoreConfigKey::Api => value.parse().map(|v| self.api = v).is_ok(),
+            AiStoreConfigKey::SkipVerifyCrt => parse_bool(&value)
+                .map(|v| self.skip_verify_crt = v)
+                .is_some(),
+            AiStoreConfigKey::AllowHttp => {
+                parse_bool(&value).map(|v| self.allow_http = v).is_some()
+            }
+            AiStoreConfigKey::S3ApiViaRoot => parse_bool(&value)
+                .map(|v| self.s3_api_via_root = v)
+                .is_some(),
+            AiStoreConfigKey::MultipartConcurrency => value
+                .parse()
+                .map(|v| self.multipart_concurrency = Some(v))
+                .is_ok(),
+            AiStoreConfigKey::CopyIfNotExists => value.parse().map(|v| self.copy_mode = v).is_ok(),
+            AiStoreConfigKey::RangeCoalesce => {
+                value.parse().map(|v| self.range_coalesce = Some(v)).is_ok()
+            }
+            AiStoreConfigKey::RangeConcurrency => value
+                .parse()
+                .map(|v| self.range_concurrency = Some(v))
+                .is_ok(),
+            AiStoreConfigKey::Checksum => value.parse().map(|v| self.checksum = Some(v)).is_ok(),
+            AiStoreConfigKey::RedirectHostMap => parse_host_map(&value)
+                .map(|v| self.redirect_host_map = v)
+                .is_some(),
+            AiStoreConfigKey::RedirectAuthHosts => {
+                self.redirect_auth_hosts = value
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|host| !host.is_empty())
+                    .map(str::to_string)
+                    .collect();
+                return self;
+            }
+            AiStoreConfigKey::Timeout => humantime::parse_duration(&value)
+                .map(|v| self.timeout = Some(v))
+                .is_ok(),
+            AiStoreConfigKey::ConnectTimeout => humantime::parse_duration(&value)
+                .map(|v| self.connect_timeout = Some(v))
+                .is_ok(),
+        };
+
+        if !parsed && self.invalid_config.is_none() {
+            self.invalid_config = Some((key, value));
+        }
+
+        self
+    }
+
+    /// Get the value of an option as it would be passed to [`AiStoreBuilder::with_config`]
+    pub fn get_config_value(&self, key: &AiStoreConfigKey) -> Option<String> {
+        match key {
+            AiStoreConfigKey::Endpoint => self.endpoint.clone(),
+            AiStoreConfigKey::Bucket => self.bucket_name.clone(),
+            AiStoreConfigKey::Provider => Some(self.provider.to_string()),
+            AiStoreConfigKey::Namespace => self.namespace.as_ref().map(|n| n.to_string()),
+            AiStoreConfigKey::Api => Some(self.api.as_str().to_string()),
+            AiStoreConfigKey::AuthnToken => self.auth_jwt_token.clone(),
+            AiStoreConfigKey::AuthnTokenFile => self.auth_jwt_token_file.clone(),
+            AiStoreConfigKey::AuthnUrl => self.authn_url.clone(),
+            AiStoreConfigKey::AuthnUsername => self.authn_username.clone(),
+            AiStoreConfigKey::AuthnPassword => self.authn_password.clone(),
+            AiStoreConfigKey::SkipVerifyCrt => Some(self.skip_verify_crt.to_string()),
+            AiStoreConfigKey::AllowHttp => Some(self.allow_http.to_string()),
+            AiStoreConfigKey::Timeout => self
+                .timeout
+                .map(|d| humantime::format_duration(d).to_string()),
+            AiStoreConfigKey::ConnectTimeout => self
+                .connect_timeout
+                .map(|d| humantime::format_duration(d).to_string()),
+            AiStoreConfigKey::S3ApiViaRoot => Some(self.s3_api_via_root.to_string()),
+            AiStoreConfigKey::MultipartConcurrency => {
+                self.multipart_concurrency.map(|v| v.to_string())
+            }
+            AiStoreConfigKey::CopyIfNotExists => Some(self.copy_mode.as_str().to_string()),
+            AiStoreConfigKey::RangeCoalesce => self.range_coalesce.map(|v| v.to_string()),
+            AiStoreConfigKey::RangeConcurrency => self.range_concurrency.map(|v| v.to_string()),
+            AiStoreConfigKey::Checksum => self.checksum.map(|c| c.as_str().to_string()),
+            AiStoreConfigKey::RedirectHostMap => (!self.redirect_host_map.is_empty()).then(|| {
+                self.redirect_host_map
+                    .iter()
+                    .map(|(from, to)| format!("{from}={to}"))
+                    .collect::<Vec<_>>()
+                    .join(",")
+            }),
+            AiStoreConfigKey::RedirectAuthHosts => {
+                (!self.redirect_auth_hosts.is_empty()).then(|| self.redirect_auth_hosts.join(","))
+            }
+        }
+    }
+
+    /// Set the bucket from a URL such as `ais://bucket`, `ais://@uuid#namespace/bucket`
+    /// or `s3://bucket` for an AWS bucket accessed through AIStore
+    ///
+    /// The scheme selects the [`Provider`]. Bucket, provider and namespace taken from
+    /// the URL override the ones set with the other methods; all but plain `ais://`
+    /// buckets need [`AiStoreApi::Native`]. An object prefix after
+    /// the bucket is ignored here; use [`crate::parse_url_opts`] to get it back.
+    pub fn with_url(mut self, url: impl Into<String>) -> Self {
+        self.url = Some(url.into());
+        self
+    }
+
+    /// Set the AIStore endpoint URL (e.g., "aistore.example.com" or "localhost:8080")
+    pub fn with_endpoint(mut self, endpoint: impl Into<String>) -> Self {
+        self.endpoint = Some(endpoint.into());
+        self
+    }
+
+    /// Set the bucket name to use
+    pub fn with_bucket_name(mut self, bucket_name: impl Into<String>) -> Self {
+        self.bucket_name = Some(bucket_name.into());
+        self
+    }
+
+    /// Set the provider of the bucket (default: [`Provider::Ais`])
+    ///
+    /// Other providers need [`AiStoreApi::Native`], see [`AiStoreApi::S3`];
+    /// [`build`](Self::build) rejects them with [`BuilderError::UnsupportedBucket`].
+    pub fn with_provider(mut self, provider: Provider) -> Self {
+        self.provider = provider;
+        self
+    }
+
+    /// Set the namespace of the bucket, e.g. to address a bucket of a remote AIS cluster
+    ///
+    /// Requires [`AiStoreApi::Native`], like a provider other than [`Provider::Ais`].
+    pub fn with_namespace(mut self, namespace: Namespace) -> Self {
+        self.namespace = Some(namespace);
+        self
+    }
+
+    /// Select the AIStore API to use (default: [`AiStoreApi::S3`])
+    pub fn with_api(mut self, api: AiStoreApi) -> Self {
+        self.api = api;
+        self
+    }
+
+    /// Set the JWT authentication token
+    pub fn with_auth_jwt_token(mut self, auth_jwt_token: impl Into<String>) -> Self {
+        self.auth_jwt_token = Some(auth_jwt_token.into());
+        self
+    }
+
+    /// Set a file to read the JWT authentication token from when building
+    ///
+    /// The file may hold the raw token or the `{"token": "..."}` JSON the AIS CLI writes.
+    pub fn with_auth_jwt_token_file(mut self, path: impl Into<String>) -> Self {
+        self.auth_jwt_token_file = Some(path.into());
+        self
+    }
+
+    /// Set the URL of the AIS AuthN server to log in to (e.g., "http://authn.example.com:52001")
+    ///
+    /// Together with [`Self::with_authn_username`] and [`Self::with_authn_password`]
+    /// this takes precedence over a static JWT: the store logs in, caches the token
+    /// and logs in again before it expires or when the cluster rejects it.
+    pub fn with_authn_url(mut self, authn_url: impl Into<String>) -> Self {
+        self.authn_url = Some(authn_url.into());
+        self
+    }
+
+    /// Set the user name to log in to AuthN with
+    pub fn with_authn_username(mut self, username: impl Into<String>) -> Self {
+        self.authn_username = Some(username.into());
+        self
+    }
+
+    /// Set the password to log in to AuthN with
+    pub fn with_authn_password(mut self, password: impl Into<String>) -> Self {
+        self.authn_password = Some(password.into());
+        self
+    }
+
+    /// Allow HTTP connections (default: false, HTTPS only)
+    pub fn with_allow_http(mut self, allow_http: bool) -> Self {
+        self.allow_http = allow_http;
+        self
+    }
+
+    /// Skip verification of the server's TLS certificate (default: false)
+    ///
+    /// Only meant for clusters with self-signed certificates.
+    pub fn with_skip_verify_crt(mut self, skip_verify_crt: bool) -> Self {
+        self.skip_verify_crt = skip_verify_crt;
+        self
+    }
+
+    /// Set the request timeout
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Set the connection timeout
+    pub fn with_connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = Some(timeout);
+        self
+    }
+
+    /// Configure S3 API path routing.
+    ///
+    /// When `true`, the S3 API is served at the root: `http(s)://{endpoint}/{bucket}`
+    /// When `false` (default), the S3 API is served at: `http(s)://{endpoint}/s3/{bucket}`
+    pub fn with_s3_api_via_root(mut self, s3_api_via_root: bool) -> Self {
+        self.s3_api_via_root = s3_api_via_root;
+        self
+    }
+
+    /// Set how many parts of a multipart upload may be in flight at once
+    /// (default: 8)
+    ///
+    /// Further `put_part` calls wait for a slot before they start sending.
+    pub fn with_multipart_concurrency(mut self, multipart_concurrency: usize) -> Self {
+        self.multipart_concurrency = Some(multipart_concurrency);
+        self
+    }
+
+    /// Set how `copy_if_not_exists` and `rename_if_not_exists` guard against
+    /// overwriting (default: [`CopyMode::Conditional`])
+    pub fn with_copy_if_not_exists(mut self, copy_mode: CopyMode) -> Self {
+        self.copy_mode = copy_mode;
+        self
+    }
+
+    /// Set the largest gap in bytes between two ranges that `get_ranges`
+    /// fetches with a single request (default: 1 MiB)
+    pub fn with_range_coalesce(mut self, range_coalesce: u64) -> Self {
+        self.range_coalesce = Some(range_coalesce);
+        self
+    }
+
+    /// Set how many range requests `get_ranges` may have in flight at once
+    /// (default: 10)
+    pub fn with_range_concurrency(mut self, range_concurrency: usize) -> Self {
+        self.range_concurrency = Some(range_concurrency);
+        self
+    }
+
+    /// Send a checksum of the data with every upload and part, and verify
+    /// reads of whole objects against the checksum the cluster reports
+    ///
+    /// Reads are verified with whichever of md5, crc32c, sha256 and xxhash the
+    /// cluster reports for the object; objects stored without a checksum are
+    /// read unverified and a warning is logged. Mismatches fail with
+    /// [`AiStoreError::ChecksumMismatch`](crate::AiStoreError::ChecksumMismatch).
+    /// Off by default.
+    ///
+    /// Only the native API reports the stored checksum on reads. With the S3
+    /// API, uploads are still checksummed but reads go unverified, and
+    /// [`build`](Self::build) logs a warning; xxhash, which S3 has no header
+    /// for, fails the build with [`BuilderError::UnsupportedChecksum`]. The
+    /// native API sends multipart uploads through the S3 API as well, so with
+    /// xxhash they fail with [`object_store::Error::NotSupported`].
+    pub fn with_checksum(mut self, algorithm: ChecksumAlgorithm) -> Self {
+        self.checksum = Some(algorithm);
+        self
+    }
+
+    /// Set how requests are retried and redirects followed
+    /// (default: [`RequestPolicy::default`])
+    pub fn with_retry_policy(mut self, retry_policy: RequestPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    /// Replace hosts in the redirects AIStore proxies send, e.g. internal pod
+    /// addresses of targets with their address behind an ingress or NAT
+    ///
+    /// Keys are `host` or `host:port` as they appear in the `Location` header;
+    /// values are `host`, `host:port` or `scheme://host[:port]`. A bare `host`
+    /// keeps the port of the redirect. IPv6 addresses go in brackets, e.g.
+    /// `[fd00::1]:8081`.
+    pub fn with_redirect_host_map<I, K, V>(mut self, map: I) -> Self
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: Into<String>,
+        V: Into<String>,
+    {
+        self.redirect_host_map = map
+            .into_iter()
+            .map(|(from, to)| (from.into(), to.into()))
+            .collect();
+        self
+    }
+
+    /// Keep sending credentials when a redirect leads to one of `hosts`
+    ///
+    /// AIStore proxies redirect object requests to targets, which run on other
+    /// hosts or ports; clusters with authentication need them listed here.
+    /// Entries are host names, `*.{domain}` or `*`, and add to the hosts of
+    /// [`RequestPolicy::redirect_auth_hosts`]. A listed host keeps the
+    /// credentials on any port.
+    pub fn with_redirect_auth_hosts<I, H>(mut self, hosts: I) -> Self
+    where
+        I: IntoIterator<Item = H>,
+        H: Into<String>,
+    {
+        self.redirect_auth_hosts = hosts.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Rewrite the target of every redirect before it is followed
+    ///
+    /// Takes precedence over [`AiStoreBuilder::with_redirect_host_map`].
+    pub fn with_redirect_rewrite(
+        mut self,
+        rewrite: impl Fn(url::Url) -> url::Url + Send + Sync + 'static,
+    ) -> Self {
+        self.redirect_rewrite = Some(RedirectRewrite::new(rewrite));
+        self
+    }
+
+    /// Build the AiStore client
+    pub fn build(mut self) -> object_store::Result<AiStore> {
+        if let Some((key, value)) = self.invalid_config {
+            return Err(BuilderError::InvalidConfigValue {
+                key: key.as_ref().to_string(),
+                value,
+            }
+            .into());
+        }
+
+        if let Some(url) = &self.url {
+            let parsed = parse::parse_url(url)?;
+            self.bucket_name = Some(parsed.bucket.name);
+            self.provider = parsed.bucket.provider;
+            self.namespace = parsed.bucket.namespace;
+        }
+
+        let bucket = self.bucket_name.ok_or(BuilderError::MissingBucketName)?;
+        let endpoint = self.endpoint.ok_or(BuilderError::MissingEndpoint)?;
+
+        let bucket = Bucket {
+            name: bucket,
+            provider: self.provider,
+            namespace: self.namespace,
+        };
+
+        // Buckets the S3 API cannot address, see `AiStoreApi::S3`
+        if self.api == AiStoreApi::S3
+            && (bucket.provider != Provider::Ais || bucket.namespace.is_some())
+        {
+            return Err(BuilderError::UnsupportedBucket {
+                bucket: bucket.to_string(),
+                api: self.api.as_str().to_string(),
+            }
+            .into());
+        }
+
+        if let (Some(algorithm), AiStoreApi::S3) = (self.checksum, self.api) {
+            if !algorithm.s3_supported() {
+                return Err(BuilderError::UnsupportedChecksum {
+                    algorithm: algorithm.as_str().to_string(),
+                    api: self.api.as_str().to_string(),
+                }
+                .into());
+            }
+            tracing::warn!(
+                checksum = algorithm.as_str(),
+                "AIStore's S3 API does not return object checksums, reads will not be verified; \
+                 use the native API to verify them"
+            );
+        }
+
+        let url = if self.s3_api_via_root {
+            endpoint.clone()
+        } else {
+            format!("{endpoint}/s3")
+        };
+
+        let mut client_builder = reqwest::Client::builder();
+
+        if let Some(timeout) = self.timeout {
+            client_builder = client_builder.timeout(timeout);
+        }
+
+        if let Some(connect_timeout) = self.connect_timeout {
+            client_builder = client_builder.connect_timeout(connect_timeout);
+        }
+
+        // Redirects from proxies to targets are followed by `HttpRequestBuilder`,
+        // which keeps PUT bodies and controls where credentials are sent
+        client_builder = client_builder
+            .redirect(reqwest::redirect::Policy::none())
+            .https_only(!self.allow_http)
+            .danger_accept_invalid_certs(self.skip_verify_crt);
+
+        let reqwest_client = client_builder
+            .build()
+            .map_err(|e| BuilderError::HttpClient { source: e })?;
+
+        let auth_jwt_token = match (self.auth_jwt_token, &self.auth_jwt_token_file) {
+            (Some(jwt), _) => Some(jwt),
+            (None, Some(path)) => Some(read_token_file(path)?),
+            (None, None) => None,
+        };
+
+        let credentials: Option<Arc<dyn TokenProvider>> =
+            match (self.authn_username, self.authn_password, auth_jwt_token) {
+                (Some(username), Some(password), _) => {
+                    let url = self
+                        .authn_url
+                        .ok_or(BuilderError::IncompleteAuthnCredentials)?;
+                    Some(Arc::new(AuthnTokenProvider::new(
+                        reqwest_client.clone(),
+                        url,
+                        username,
+                        password,
+                        self.retry_policy.clone(),
+                    )))
+                }
+                (None, None, Some(jwt)) => {
+                    reqwest::header::HeaderValue::from_str(&format!("Bearer {jwt}")).map_err(
+                        |e| BuilderError::InvalidAuthToken {
+                            message: e.to_string(),
+                        },
+                    )?;
+                    Some(Arc::new(StaticTokenProvider::new(jwt)))
+                }
+                // An AuthN server without credentials to log in with is a mistake
+                (None, None, None) if self.authn_url.is_some() => {
+                    return Err(BuilderError::IncompleteAuthnCredentials.into())
+                }
+                (None, None, None) => None,
+                _ => return Err(BuilderError::IncompleteAuthnCredentials.into()),
+            };
+
+        let redirect_rewrite = self.redirect_rewrite.or_else(|| {
+            (!self.redirect_host_map.is_empty())
+                .then(|| RedirectRewrite::from_host_map(self.redirect_host_map))
+        });
+
+        self.retry_policy
+            .redirect_auth_hosts
+            .extend(self.redirect_auth_hosts);
+
+        let http_client = HttpClient::new(
+            reqwest_client,
+            credentials,
+            self.retry_policy,
+            redirect_rewrite,
+        );
+
+        let s3_client = S3Client::new(
+            S3Config {
+                url,
+                bucket: bucket.name.clone(),
+                checksum: self.checksum,
+            },
+            http_client.clone(),
+        );
+
+        let client: Arc<dyn Backend> = match self.api {
+            AiStoreApi::S3 => Arc::new(s3_client),
+            AiStoreApi::Native => {
+                let native_config = NativeConfig {
+                    endpoint,
+                    bucket: bucket.clone(),
+                    checksum: self.checksum,
+                };
+                Arc::new(NativeClient::new(native_config, http_client, s3_client))
+            }
+        };
+
+        Ok(AiStore {
+            client,
+            bucket,
+            multipart_concurrency: self
+                .multipart_concurrency
+                .unwrap_or(DEFAULT_MULTIPART_CONCURRENCY),
+            copy_mode: self.copy_mode,
+            range_coalesce: self
+                .range_coalesce
+                .unwrap_or(object_store::OBJECT_STORE_COALESCE_DEFAULT),
+            range_concurrency: self.range_concurrency.unwrap_or(DEFAULT_RANGE_CONCURRENCY),
+        })
+    }
+}
+
+/// Parse `from=to` pairs separated by `,`
+fn parse_host_map(value: &str) -> Option<Vec<(String, String)>> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| {
+            let (from, to) = entry.split_once('=')?;
+            Some((from.trim().to_string(), to.trim().to_string()))
+        })
+        .collect()
+}
+
+/// Parse a boolean option the way other `object_store` backends do
+fn parse_bool(value: &str) -> Option<bool> {
+    match value.to_ascii_lowercase().as_str() {
+        "true" | "1" | "yes" | "y" | "on" => Some(true),
+        "false" | "0" | "no" | "n" | "off" => Some(false),
+        _ => None,
+    }
+}
+
+/// Read a token file, which holds either the raw JWT or `{"token": "..."}`
+fn read_token_file(path: &str) -> Result<String, BuilderError> {
+    #[derive(serde::Deserialize)]
+    struct TokenFile {
+        token: String,
+    }
+
+    let contents = std::fs::read_to_string(path).map_err(|e| BuilderError::TokenFile {
+        path: path.to_string(),
+        source: e,
+    })?;
+
+    Ok(match serde_json::from_str::<TokenFile>(&contents) {
+        Ok(file) => file.token,
+        Err(_) => contents.trim().to_string(),
+    })
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum BuilderError {
+    #[error("Missing bucket name")]
+    MissingBucketName,
+
+    #[error("Missing endpoint")]
+    MissingEndpoint,
+
+    #[error("Invalid value for {key}: {value}")]
+    InvalidConfigValue { key: String, value: String },
+
+    #[error("Invalid API: {api}")]
+    InvalidApi { api: String },
+
+    #[error("Checksum {algorithm} is not supported with the {api} API")]
+    UnsupportedChecksum { algorithm: String, api: String },
+
+    #[error("Bucket {bucket} is not supported with the {api} API, use the native API")]
+    UnsupportedBucket { bucket: String, api: String },
+
+    #[error("Failed to read token file {path}: {source}")]
+    TokenFile {
+        path: String,
+        #[source]
+        source: std::io::Error,
+    },
+
+    #[error("Invalid URL: {url}")]
+    InvalidUrl { url: String },
+
+    #[error("Invalid bucket provider: {provider}")]
+    InvalidProvider { provider: String },
+
+    #[error("Invalid bucket namespace: {namespace}")]
+    InvalidNamespace { namespace: String },
+
+    #[error("Invalid auth token: {message}")]
+    InvalidAuthToken { message: String },
+
+    #[error("AuthN login requires a URL, a user name and a password")]
+    IncompleteAuthnCredentials,
+
+    #[error("Failed to build HTTP client: {source}")]
+    HttpClient {
+        #[source]
+        source: reqwest::Error,
+    },
+}
+
+impl From<BuilderError> for object_store::Error {
+    fn from(source: BuilderError) -> Self {
+        Self::Generic {
+            store: "aistore",
+            source: Box::new(source),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn config_keys_parse_from_aliases() {
+        for (key, expected) in [
+            ("ais_endpoint", AiStoreConfigKey::Endpoint),
+            ("endpoint", AiStoreConfigKey::Endpoint),
+            ("ais_bucket_name", AiStoreConfigKey::Bucket),
+            ("bucket", AiStoreConfigKey::Bucket),
+            ("token", AiStoreConfigKey::AuthnToken),
+            ("authn_token_file", AiStoreConfigKey::AuthnTokenFile),
+            ("username", AiStoreConfigKey::AuthnUsername),
+            ("skip_verify_crt", AiStoreConfigKey::SkipVerifyCrt),
+            ("ais_copy_if_not_exists", AiStoreConfigKey::CopyIfNotExists),
+        ] {
+            assert_eq!(key.parse::<AiStoreConfigKey>().unwrap(), expected, "{key}");
+        }
+
+        // Canonical names parse back to their key
+        let key = AiStoreConfigKey::RangeConcurrency;
+        assert_eq!(key.as_ref().parse::<AiStoreConfigKey>().unwrap(), key);
+
+        assert!(matches!(
+            "AIS_ENDPOINT".parse::<AiStoreConfigKey>(),
+            Err(object_store::Error::UnknownConfigurationKey { .. })
+        ));
+    }
+
+    #[test]
+    fn bool_parsing() {
+        for value in ["true", "TRUE", "1", "yes", "Y", "on"] {
+            assert_eq!(parse_bool(value), Some(true), "{value}");
+        }
+        for value in ["false", "False", "0", "no", "n", "OFF"] {
+            assert_eq!(parse_bool(value), Some(false), "{value}");
+        }
+        assert_eq!(parse_bool(""), None);
+        assert_eq!(parse_bool("maybe"), None);
+    }
+
+    #[test]
+    fn from_env_reads_ais_variables() {
+        let vars = [
+            ("AIS_ENDPOINT", "http://ais.example:8080"),
+            ("AIS_SKIP_VERIFY_CRT", "yes"),
+            ("AIS_NOT_A_KEY", "ignored"),
+            ("BUCKET", "ignored"),
+        ];
+
+        let builder = AiStoreBuilder::from_vars(vars.map(|(k, v)| (k.into(), v.into())));
+        assert_eq!(
+            builder.get_config_value(&AiStoreConfigKey::Endpoint),
+            Some("http://ais.example:8080".to_string())
+        );
+        assert_eq!(
+            builder.get_config_value(&AiStoreConfigKey::SkipVerifyCrt),
+            Some("true".to_string())
+        );
+        assert!(builder.invalid_config.is_none());
+        assert_eq!(builder.get_config_value(&AiStoreConfigKey::Bucket), None);
+    }
+
+    #[test]
+    fn authn_url_requires_credentials() {
+        let error = AiStoreBuilder::new()
+            .with_endpoint("http://localhost:8080")
+            .with_bucket_name("bucket")
+            .with_authn_url("http://localhost:52001")
+            .build()
+            .unwrap_err();
+
+        assert!(
+            error.to_string().contains("AuthN login requires"),
+            "{error}"
+        );
+    }
+
+    #[test]
+    fn config_values_round_trip() {
+        for (key, value) in [
+            (AiStoreConfigKey::Endpoint, "http://localhost:8080"),
+            (AiStoreConfigKey::Bucket, "bucket"),
+            (AiStoreConfigKey::Provider, "gcp"),
+            (AiStoreConfigKey::Namespace, "@uuid#ns"),
+            (AiStoreConfigKey::Api, "native"),
+            (AiStoreConfigKey::AllowHttp, "true"),
+            (AiStoreConfigKey::Timeout, "30s"),
+            (AiStoreConfigKey::MultipartConcurrency, "4"),
+            (AiStoreConfigKey::CopyIfNotExists, "head-then-copy"),
+            (AiStoreConfigKey::RangeCoalesce, "1024"),
+            (AiStoreConfigKey::Checksum, "crc32c"),
+        ] {
+            let builder = AiStoreBuilder::new().with_config(key, value);
+            assert_eq!(
+                builder.get_config_value(&key),
+                Some(value.to_string()),
+                "{key:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn invalid_config_values_fail_the_build() {
+        let error = AiStoreBuilder::new()
+            .with_endpoint("http://localhost:8080")
+            .with_bucket_name("bucket")
+            .with_config(AiStoreConfigKey::Timeout, "soon")
+            .with_config(AiStoreConfigKey::AllowHttp, "maybe")
+            .build()
+            .unwrap_err();
+
+        // The first invalid value is reported
+        let message = error.to_string();
+        assert!(message.contains("ais_timeout: soon"), "{message}");
+    }
+
+    #[test]
+    fn host_map_parsing() {
+        assert_eq!(
+            parse_host_map(" 10.0.0.1:8081 = gw:9000 ,10.0.0.2=https://gw2,"),
+            Some(vec![
+                ("10.0.0.1:8081".to_string(), "gw:9000".to_string()),
+                ("10.0.0.2".to_string(), "https://gw2".to_string()),
+            ])
+        );
+        assert_eq!(parse_host_map(""), Some(vec![]));
+        assert_eq!(parse_host_map("10.0.0.1"), None);
+    }
+
+    #[test]
+    fn host_map_config_key() {
+        let builder =
+            AiStoreBuilder::new().with_config(AiStoreConfigKey::RedirectHostMap, "10.0.0.1=gw");
+        assert_eq!(
+            builder.get_config_value(&AiStoreConfigKey::RedirectHostMap),
+            Some("10.0.0.1=gw".to_string())
+        );
+    }
+
+    #[test]
+    fn xxhash_requires_the_native_api() {
+        let builder = || {
+            AiStoreBuilder::new()
+                .with_endpoint("http://localhost:8080")
+                .with_bucket_name("bucket")
+                .with_checksum(ChecksumAlgorithm::Xxhash)
+        };
+
+        let error = builder().build().unwrap_err();
+        assert!(error.to_string().contains("xxhash"), "{error}");
+        builder().with_api(AiStoreApi::Native).build().unwrap();
+    }
+
+    #[test]
+    fn redirect_auth_hosts_config_key() {
+        let builder = AiStoreBuilder::new().with_config(
+            AiStoreConfigKey::RedirectAuthHosts,
+            " t1.example, *.ais.internal ,",
+        );
+        assert_eq!(
+            builder.redirect_auth_hosts,
+            ["t1.example", "*.ais.internal"]
+        );
+        assert_eq!(
+            builder.get_config_value(&AiStoreConfigKey::RedirectAuthHosts),
+            Some("t1.example,*.ais.internal".to_string())
+        );
+    }
+
+    /// A target that accepts everything, a proxy that redirects to it, and the
+    /// `Authorization` header the target received from a store with a token
+    async fn redirected_authorization(
+        configure: impl FnOnce(AiStoreBuilder) -> AiStoreBuilder,
+    ) -> Option<String> {
+        use crate::mock::{MockServer, Reply};
+        use object_store::ObjectStore;
+
+        let mut target = MockServer::bind().await;
+        target.serve(|_| Reply::status(200));
+
+        let mut proxy = MockServer::bind().await;
+        let location = target.url().to_string();
+        proxy.serve(move |request| {
+            Reply::status(307).header("location", format!("{location}{}", request.target))
+        });
+
+        // The proxy is asked as `localhost`, the target is on `127.0.0.1`
+        let builder = AiStoreBuilder::new()
+            .with_endpoint(proxy.url().replace("127.0.0.1", "localhost"))
+            .with_bucket_name("bucket")
+            .with_allow_http(true)
+            .with_auth_jwt_token("secret");
+        let store = configure(builder).build().unwrap();
+
+        store
+            .put(&object_store::path::Path::from("key"), "data".into())
+            .await
+            .unwrap();
+
+        let requests = target.requests();
+        assert_eq!(requests[0].body, b"data");
+        requests[0].header("authorization").map(str::to_string)
+    }
+
+    #[tokio::test]
+    async fn credentials_follow_redirects_to_listed_hosts() {
+        assert_eq!(redirected_authorization(|builder| builder).await, None);
+
+        let authorization =
+            redirected_authorization(|builder| builder.with_redirect_auth_hosts(["127.0.0.1"]))
+                .await;
+        assert_eq!(authorization.as_deref(), Some("Bearer secret"));
+
+        let authorization = redirected_authorization(|builder| {
+            builder.with_config(AiStoreConfigKey::RedirectAuthHosts, "127.0.0.1")
+        })
+        .await;
+        assert_eq!(authorization.as_deref(), Some("Bearer secret"));
+    }
+}
@@ -0,0 +1,308 @@
+//! Optional end-to-end checksums of object data
+
+use std::str::FromStr;
+
+use base64::Engine;
+use bytes::Bytes;
+use futures::stream::BoxStream;
+use futures::StreamExt;
+use md5::Digest;
+use object_store::{path::Path, PutPayload};
+use reqwest::header::HeaderMap;
+
+use crate::backend::MetadataHeaders;
+use crate::error::AiStoreError;
+use crate::request::HttpRequestBuilder;
+use crate::{AiStoreConfigKey, BuilderError};
+
+/// Checksum sent with uploads and verified on downloads
+///
+/// These are the algorithms AIStore can store per object. The S3 API has no
+/// header for xxhash, so it can only be used with the native API.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChecksumAlgorithm {
+    Md5,
+    Crc32c,
+    Sha256,
+    /// 64-bit xxHash with seed 0, AIStore's default
+    Xxhash,
+}
+
+impl ChecksumAlgorithm {
+    /// The name AIStore uses for this algorithm in `ais-checksum-type`
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ChecksumAlgorithm::Md5 => "md5",
+            ChecksumAlgorithm::Crc32c => "crc32c",
+            ChecksumAlgorithm::Sha256 => "sha256",
+            ChecksumAlgorithm::Xxhash => "xxhash",
+        }
+    }
+
+    /// Header carrying the base64 checksum in the S3 API
+    fn s3_header(&self) -> Option<&'static str> {
+        match self {
+            ChecksumAlgorithm::Md5 => Some("content-md5"),
+            ChecksumAlgorithm::Crc32c => Some("x-amz-checksum-crc32c"),
+            ChecksumAlgorithm::Sha256 => Some("x-amz-checksum-sha256"),
+            ChecksumAlgorithm::Xxhash => None,
+        }
+    }
+
+    /// Whether uploads through the S3 API can carry this checksum
+    pub(crate) fn s3_supported(&self) -> bool {
+        self.s3_header().is_some()
+    }
+}
+
+impl FromStr for ChecksumAlgorithm {
+    type Err = BuilderError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "md5" => Ok(ChecksumAlgorithm::Md5),
+            "crc32c" => Ok(ChecksumAlgorithm::Crc32c),
+            "sha256" => Ok(ChecksumAlgorithm::Sha256),
+            // Older clusters report the same unseeded XXH64 as `xxhash2`
+            "xxhash" | "xxhash2" => Ok(ChecksumAlgorithm::Xxhash),
+            _ => Err(BuilderError::InvalidConfigValue {
+                key: AiStoreConfigKey::Checksum.as_ref().to_string(),
+                value: s.to_string(),
+            }),
+        }
+    }
+}
+
+/// Incremental checksum of a byte stream
+pub(crate) enum Hasher {
+    Md5(md5::Md5),
+    Crc32c(u32),
+    Sha256(sha2::Sha256),
+    Xxhash(xxhash_rust::xxh64::Xxh64),
+}
+
+impl Hasher {
+    pub(crate) fn new(algorithm: ChecksumAlgorithm) -> Self {
+        match algorithm {
+            ChecksumAlgorithm::Md5 => Hasher::Md5(md5::Md5::new()),
+            ChecksumAlgorithm::Crc32c => Hasher::Crc32c(0),
+            ChecksumAlgorithm::Sha256 => Hasher::Sha256(sha2::Sha256::new()),
+            ChecksumAlgorithm::Xxhash => Hasher::Xxhash(xxhash_rust::xxh64::Xxh64::new(0)),
+        }
+    }
+
+    pub(crate) fn update(&mut self, data: &[u8]) {
+        match self {
+            Hasher::Md5(hasher) => hasher.update(data),
+            Hasher::Crc32c(crc) => *crc = crc32c::crc32c_append(*crc, data),
+            Hasher::Sha256(hasher) => hasher.update(data),
+            Hasher::Xxhash(hasher) => hasher.update(data),
+        }
+    }
+
+    pub(crate) fn finalize(self) -> Vec<u8> {
+        match self {
+            Hasher::Md5(hasher) => hasher.finalize().to_vec(),
+            Hasher::Crc32c(crc) => crc.to_be_bytes().to_vec(),
+            Hasher::Sha256(hasher) => hasher.finalize().to_vec(),
+            Hasher::Xxhash(hasher) => hasher.digest().to_be_bytes().to_vec(),
+        }
+    }
+}
+
+/// Checksum of an upload body
+pub(crate) fn payload_checksum(algorithm: ChecksumAlgorithm, payload: &PutPayload) -> Vec<u8> {
+    let mut hasher = Hasher::new(algorithm);
+    for chunk in payload.iter() {
+        hasher.update(chunk);
+    }
+    hasher.finalize()
+}
+
+/// Send `digest` with an upload so the cluster rejects corrupted data
+pub(crate) fn apply_checksum(
+    request: HttpRequestBuilder,
+    algorithm: ChecksumAlgorithm,
+    digest: &[u8],
+    flavor: MetadataHeaders,
+) -> HttpRequestBuilder {
+    match flavor {
+        // The builder rejects algorithms the S3 API has no header for
+        MetadataHeaders::S3 => match algorithm.s3_header() {
+            Some(header) => request.header(
+                header,
+                base64::engine::general_purpose::STANDARD.encode(digest),
+            ),
+            None => request,
+        },
+        MetadataHeaders::Native => request
+            .header("ais-checksum-type", algorithm.as_str())
+            .header("ais-checksum-value", hex::encode(digest)),
+    }
+}
+
+/// The checksum of the whole object the cluster reports in a GET response
+///
+/// Any algorithm this crate can compute is accepted, as objects keep the
+/// checksum they were stored with; `preferred` wins if several are reported.
+/// Logs a warning when there is nothing to verify against.
+pub(crate) fn expected_checksum(
+    path: &Path,
+    headers: &HeaderMap,
+    preferred: ChecksumAlgorithm,
+) -> Option<(ChecksumAlgorithm, Vec<u8>)> {
+    let header = |name: &str| headers.get(name).and_then(|v| v.to_str().ok());
+
+    let reported = header("ais-checksum-type");
+    if let Some(algorithm) = reported.and_then(|t| t.parse::<ChecksumAlgorithm>().ok()) {
+        if let Some(value) = header("ais-checksum-value").and_then(|v| decode(algorithm, v)) {
+            return Some((algorithm, value));
+        }
+    }
+
+    // `Content-MD5` is not returned on reads, and the ETag of multipart
+    // uploads is not an MD5 of the object
+    let s3 = [
+        preferred,
+        ChecksumAlgorithm::Crc32c,
+        ChecksumAlgorithm::Sha256,
+    ]
+    .into_iter()
+    .filter(|algorithm| *algorithm != ChecksumAlgorithm::Md5)
+    .find_map(|algorithm| {
+        header(algorithm.s3_header()?)
+            .and_then(|v| base64::engine::general_purpose::STANDARD.decode(v).ok())
+            .map(|value| (algorithm, value))
+    });
+
+    if s3.is_none() {
+        tracing::warn!(
+            %path,
+            checksum_type = reported.unwrap_or("none"),
+            "Cannot verify the checksum of the object, reading it unverified"
+        );
+    }
+
+    s3
+}
+
+/// Decode a hex `ais-checksum-value`
+fn decode(algorithm: ChecksumAlgorithm, value: &str) -> Option<Vec<u8>> {
+    match algorithm {
+        // Some AIStore versions format xxhash as a number, without leading zeros
+        ChecksumAlgorithm::Xxhash => u64::from_str_radix(value, 16)
+            .ok()
+            .map(|hash| hash.to_be_bytes().to_vec()),
+        _ => hex::decode(value).ok(),
+    }
+}
+
+struct VerifyState {
+    stream: BoxStream<'static, object_store::Result<Bytes>>,
+    path: Path,
+    expected: Vec<u8>,
+    /// `None` once the stream has ended or failed
+    hasher: Option<Hasher>,
+}
+
+/// Pass `stream` through, failing with [`AiStoreError::ChecksumMismatch`] at the
+/// end if the data does not hash to `expected`
+pub(crate) fn verify_stream(
+    stream: BoxStream<'static, object_store::Result<Bytes>>,
+    path: Path,
+    algorithm: ChecksumAlgorithm,
+    expected: Vec<u8>,
+) -> BoxStream<'static, object_store::Result<Bytes>> {
+    let state = VerifyState {
+        stream,
+        path,
+        expected,
+        hasher: Some(Hasher::new(algorithm)),
+    };
+
+    futures::stream::unfold(state, |mut state| async move {
+        let hasher = state.hasher.as_mut()?;
+
+        match state.stream.next().await {
+            Some(Ok(bytes)) => {
+                hasher.update(&bytes);
+                Some((Ok(bytes), state))
+            }
+            Some(Err(e)) => {
+                state.hasher = None;
+                Some((Err(e), state))
+            }
+            None => {
+                let actual = state.hasher.take()?.finalize();
+                if actual == state.expected {
+                    return None;
+                }
+
+                let error = AiStoreError::ChecksumMismatch {
+                    path: state.path.clone(),
+                    expected: hex::encode(&state.expected),
+                    actual: hex::encode(actual),
+                };
+                Some((Err(error.into()), state))
+            }
+        }
+    })
+    .boxed()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reported_xxhash_is_verified() {
+        let path = Path::from("object");
+        let digest = payload_checksum(ChecksumAlgorithm::Xxhash, &PutPayload::from_static(b"abc"));
+        assert_eq!(hex::encode(&digest), "44bc2cf5ad770999");
+
+        let mut headers = HeaderMap::new();
+        headers.insert("ais-checksum-type", "xxhash".parse().unwrap());
+        headers.insert("ais-checksum-value", "44bc2cf5ad770999".parse().unwrap());
+        let expected = expected_checksum(&path, &headers, ChecksumAlgorithm::Md5);
+        assert_eq!(expected, Some((ChecksumAlgorithm::Xxhash, digest)));
+
+        // Leading zeros may be left out
+        headers.insert("ais-checksum-type", "xxhash2".parse().unwrap());
+        headers.insert("ais-checksum-value", "abc".parse().unwrap());
+        let expected = expected_checksum(&path, &headers, ChecksumAlgorithm::Md5);
+        assert_eq!(
+            expected,
+            Some((ChecksumAlgorithm::Xxhash, 0xabcu64.to_be_bytes().to_vec()))
+        );
+    }
+
+    #[tokio::test]
+    async fn streams_that_do_not_match_fail_at_the_end() {
+        let chunks = || {
+            futures::stream::iter([Ok(Bytes::from_static(b"ab")), Ok(Bytes::from_static(b"c"))])
+                .boxed()
+        };
+        let path = Path::from("object");
+        let digest = payload_checksum(ChecksumAlgorithm::Md5, &PutPayload::from_static(b"abc"));
+
+        let verified: Vec<_> =
+            verify_stream(chunks(), path.clone(), ChecksumAlgorithm::Md5, digest)
+                .collect()
+                .await;
+        assert_eq!(verified.len(), 2);
+        assert!(verified.iter().all(|chunk| chunk.is_ok()));
+
+        let mut verified = verify_stream(chunks(), path, ChecksumAlgorithm::Md5, vec![0; 16]);
+        assert_eq!(verified.next().await.unwrap().unwrap().as_ref(), b"ab");
+        assert_eq!(verified.next().await.unwrap().unwrap().as_ref(), b"c");
+        let err = verified.next().await.unwrap().unwrap_err();
+        let object_store::Error::Generic { source, .. } = err else {
+            panic!("{err}");
+        };
+        assert!(matches!(
+            source.downcast_ref::<AiStoreError>(),
+            Some(AiStoreError::ChecksumMismatch { .. })
+        ));
+        assert!(verified.next().await.is_none());
+    }
+}
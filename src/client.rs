@@ -1,477 +1,957 @@
-use std::ops::Range;
-
-use bytes::Bytes;
-use chrono::{DateTime, Utc};
-use futures::TryStreamExt;
-use object_store::{
-    path::Path, GetOptions, GetRange, GetResult, GetResultPayload, ObjectMeta, PutPayload,
-    PutResult,
-};
-use reqwest::{Response, StatusCode};
-
-use crate::error::AiStoreError;
-use crate::request::{ClientExt, RequestBody};
-use crate::xml::{self, CompleteMultipartUploadRequest, ListBucketResult};
-
-#[derive(Debug, Clone)]
-pub(crate) struct S3Config {
-    pub url: String,
-}
-
-#[derive(Debug, Clone)]
-pub(crate) struct S3Client {
-    config: S3Config,
-    client: reqwest::Client,
-}
-
-impl S3Client {
-    pub(crate) fn new(config: S3Config, client: reqwest::Client) -> Self {
-        Self { config, client }
-    }
-
-    fn object_url(&self, path: &Path) -> String {
-        format!("{}/{}", self.config.url, path.as_ref())
-    }
-
-    fn bucket_url(&self) -> &str {
-        &self.config.url
-    }
-
-    pub(crate) async fn put_object(
-        &self,
-        path: &Path,
-        payload: PutPayload,
-    ) -> Result<PutResult, AiStoreError> {
-        let url = self.object_url(path);
-        let content_length = payload.content_length();
-
-        let response = self
-            .client
-            .put_with_retry(url)
-            .header(
-                reqwest::header::CONTENT_LENGTH.as_str(),
-                content_length.to_string(),
-            )
-            .body(RequestBody::Payload(payload))
-            .send()
-            .await?;
-
-        let status = response.status();
-        if !status.is_success() {
-            return Err(Self::handle_error_response(response).await);
-        }
-
-        let etag = response
-            .headers()
-            .get(reqwest::header::ETAG)
-            .and_then(|v| v.to_str().ok())
-            .map(|s| s.trim_matches('"').to_string());
-
-        let version = response
-            .headers()
-            .get("x-ais-version")
-            .and_then(|v| v.to_str().ok())
-            .map(|s| s.to_string());
-
-        Ok(PutResult {
-            e_tag: etag,
-            version,
-        })
-    }
-
-    pub(crate) async fn get_object(
-        &self,
-        path: &Path,
-        options: GetOptions,
-    ) -> Result<GetResult, AiStoreError> {
-        let url = self.object_url(path);
-
-        let mut request = if options.head {
-            self.client.head_with_retry(&url)
-        } else {
-            self.client.get_with_retry(&url)
-        };
-
-        if let Some(range) = &options.range {
-            let range_header = match range {
-                GetRange::Bounded(r) => format!("bytes={}-{}", r.start, r.end.saturating_sub(1)),
-                GetRange::Offset(offset) => format!("bytes={}-", offset),
-                GetRange::Suffix(length) => format!("bytes=-{}", length),
-            };
-            request = request.header(reqwest::header::RANGE.to_string(), range_header);
-        }
-
-        if let Some(if_match) = &options.if_match {
-            request = request.header(
-                reqwest::header::IF_MATCH.to_string(),
-                if_match.as_ref() as &str,
-            );
-        }
-
-        if let Some(if_none_match) = &options.if_none_match {
-            request = request.header(
-                reqwest::header::IF_NONE_MATCH.to_string(),
-                if_none_match.as_ref() as &str,
-            );
-        }
-
-        if let Some(if_modified_since) = &options.if_modified_since {
-            request = request.header(
-                reqwest::header::IF_MODIFIED_SINCE.to_string(),
-                if_modified_since
-                    .format("%a, %d %b %Y %H:%M:%S GMT")
-                    .to_string(),
-            );
-        }
-
-        if let Some(if_unmodified_since) = &options.if_unmodified_since {
-            request = request.header(
-                reqwest::header::IF_UNMODIFIED_SINCE.to_string(),
-                if_unmodified_since
-                    .format("%a, %d %b %Y %H:%M:%S GMT")
-                    .to_string(),
-            );
-        }
-
-        let response = request.send().await?;
-        let status = response.status();
-
-        if status == StatusCode::NOT_MODIFIED {
-            return Err(AiStoreError::NotModified { path: path.clone() });
-        }
-
-        if status == StatusCode::PRECONDITION_FAILED {
-            return Err(AiStoreError::PreconditionFailed { path: path.clone() });
-        }
-
-        if !status.is_success() {
-            return Err(Self::handle_error_response(response).await);
-        }
-
-        let meta = Self::extract_object_meta(path, &response)?;
-        let content_range = Self::parse_content_range(&response);
-
-        let range = content_range.unwrap_or(0..meta.size);
-
-        if options.head {
-            Ok(GetResult {
-                meta,
-                range,
-                attributes: Default::default(),
-                payload: GetResultPayload::Stream(Box::pin(futures::stream::empty())),
-            })
-        } else {
-            let stream = response.bytes_stream();
-            let stream = stream.map_err(|e| object_store::Error::Generic {
-                store: "aistore",
-                source: Box::new(e),
-            });
-
-            Ok(GetResult {
-                meta,
-                range,
-                attributes: Default::default(),
-                payload: GetResultPayload::Stream(Box::pin(stream)),
-            })
-        }
-    }
-
-    pub(crate) async fn head_object(&self, path: &Path) -> Result<ObjectMeta, AiStoreError> {
-        let url = self.object_url(path);
-
-        let response = self.client.head_with_retry(url).send().await?;
-
-        let status = response.status();
-        if !status.is_success() {
-            return Err(Self::handle_error_response(response).await);
-        }
-
-        Self::extract_object_meta(path, &response)
-    }
-
-    pub(crate) async fn delete_object(&self, path: &Path) -> Result<(), AiStoreError> {
-        let url = self.object_url(path);
-
-        let response = self.client.delete_with_retry(url).send().await?;
-
-        let status = response.status();
-        if !status.is_success() {
-            return Err(Self::handle_error_response(response).await);
-        }
-
-        Ok(())
-    }
-
-    pub(crate) async fn list_objects(
-        &self,
-        prefix: Option<&str>,
-        continuation_token: Option<&str>,
-        max_keys: Option<u32>,
-    ) -> Result<ListBucketResult, AiStoreError> {
-        let url = self.bucket_url();
-
-        let mut query_params = vec![("list-type".to_string(), "2".to_string())];
-
-        if let Some(prefix) = prefix {
-            query_params.push(("prefix".to_string(), prefix.to_string()));
-        }
-
-        if let Some(token) = continuation_token {
-            query_params.push(("continuation-token".to_string(), token.to_string()));
-        }
-
-        if let Some(max) = max_keys {
-            query_params.push(("max-keys".to_string(), max.to_string()));
-        }
-
-        let response = self
-            .client
-            .get_with_retry(url)
-            .query_params(query_params)
-            .send()
-            .await?;
-
-        let status = response.status();
-        if !status.is_success() {
-            return Err(Self::handle_error_response(response).await);
-        }
-
-        let body = response
-            .text()
-            .await
-            .map_err(|e| AiStoreError::Request { source: e })?;
-
-        xml::from_xml(&body).map_err(|e| AiStoreError::InvalidResponse {
-            message: format!("Failed to parse ListObjectsV2 response: {}", e),
-        })
-    }
-
-    pub(crate) async fn copy_object(&self, from: &Path, to: &Path) -> Result<(), AiStoreError> {
-        let url = self.object_url(to);
-        let source = self.object_url(from);
-
-        let response = self
-            .client
-            .put_with_retry(url)
-            .header("x-amz-copy-source", source)
-            .send()
-            .await?;
-
-        let status = response.status();
-        if !status.is_success() {
-            return Err(Self::handle_error_response(response).await);
-        }
-
-        Ok(())
-    }
-
-    pub(crate) async fn initiate_multipart_upload(
-        &self,
-        path: &Path,
-    ) -> Result<String, AiStoreError> {
-        let url = format!("{}?uploads", self.object_url(path));
-
-        let response = self.client.post_with_retry(url).send().await?;
-
-        let status = response.status();
-        if !status.is_success() {
-            return Err(Self::handle_error_response(response).await);
-        }
-
-        if let Some(upload_id) = response.headers().get("x-ais-upload-id") {
-            return upload_id.to_str().map(|s| s.to_string()).map_err(|_| {
-                AiStoreError::InvalidResponse {
-                    message: "Invalid upload ID header".to_string(),
-                }
-            });
-        }
-
-        let body = response
-            .text()
-            .await
-            .map_err(|e| AiStoreError::Request { source: e })?;
-
-        let result: xml::InitiateMultipartUploadResult =
-            xml::from_xml(&body).map_err(|e| AiStoreError::InvalidResponse {
-                message: format!("Failed to parse InitiateMultipartUpload response: {}", e),
-            })?;
-
-        Ok(result.upload_id)
-    }
-
-    pub(crate) async fn upload_part(
-        &self,
-        path: &Path,
-        upload_id: &str,
-        part_number: u32,
-        data: Bytes,
-    ) -> Result<String, AiStoreError> {
-        let url = format!(
-            "{}?partNumber={}&uploadId={}",
-            self.object_url(path),
-            part_number,
-            upload_id
-        );
-
-        let response = self
-            .client
-            .put_with_retry(url)
-            .body(RequestBody::Bytes(data))
-            .send()
-            .await?;
-
-        let status = response.status();
-        if !status.is_success() {
-            return Err(Self::handle_error_response(response).await);
-        }
-
-        let etag = response
-            .headers()
-            .get(reqwest::header::ETAG)
-            .and_then(|v| v.to_str().ok())
-            .map(|s| s.trim_matches('"').to_string())
-            .ok_or_else(|| AiStoreError::InvalidResponse {
-                message: "No ETag in part upload response".to_string(),
-            })?;
-
-        Ok(etag)
-    }
-
-    pub(crate) async fn complete_multipart_upload(
-        &self,
-        path: &Path,
-        upload_id: &str,
-        parts: Vec<(u32, String)>,
-    ) -> Result<PutResult, AiStoreError> {
-        let url = format!("{}?uploadId={}", self.object_url(path), upload_id);
-
-        let request_body = CompleteMultipartUploadRequest::new(parts);
-        let xml = xml::to_xml(&request_body).map_err(|e| AiStoreError::InvalidResponse {
-            message: format!("Failed to serialize CompleteMultipartUpload request: {}", e),
-        })?;
-
-        let response = self
-            .client
-            .post_with_retry(url)
-            .header(reqwest::header::CONTENT_TYPE.to_string(), "application/xml")
-            .body(RequestBody::Text(xml))
-            .send()
-            .await?;
-
-        let status = response.status();
-        if !status.is_success() {
-            return Err(Self::handle_error_response(response).await);
-        }
-
-        let etag = response
-            .headers()
-            .get(reqwest::header::ETAG)
-            .and_then(|v| v.to_str().ok())
-            .map(|s| s.trim_matches('"').to_string());
-
-        let version = response
-            .headers()
-            .get("x-ais-version")
-            .and_then(|v| v.to_str().ok())
-            .map(|s| s.to_string());
-
-        Ok(PutResult {
-            e_tag: etag,
-            version,
-        })
-    }
-
-    /// Abort a multipart upload
-    pub(crate) async fn abort_multipart_upload(
-        &self,
-        path: &Path,
-        upload_id: &str,
-    ) -> Result<(), AiStoreError> {
-        let url = format!("{}?uploadId={}", self.object_url(path), upload_id);
-
-        let response = self.client.delete_with_retry(url).send().await?;
-
-        let status = response.status();
-        if !status.is_success() {
-            return Err(Self::handle_error_response(response).await);
-        }
-
-        Ok(())
-    }
-
-    fn extract_object_meta(path: &Path, response: &Response) -> Result<ObjectMeta, AiStoreError> {
-        let headers = response.headers();
-
-        let size = headers
-            .get(reqwest::header::CONTENT_LENGTH)
-            .and_then(|v| v.to_str().ok())
-            .and_then(|s| s.parse::<u64>().ok())
-            .unwrap_or(0);
-
-        let last_modified = headers
-            .get(reqwest::header::LAST_MODIFIED)
-            .and_then(|v| v.to_str().ok())
-            .and_then(|s| DateTime::parse_from_rfc2822(s).ok())
-            .map(|dt| dt.with_timezone(&Utc))
-            .unwrap_or_else(Utc::now);
-
-        let e_tag = headers
-            .get(reqwest::header::ETAG)
-            .and_then(|v| v.to_str().ok())
-            .map(|s| s.trim_matches('"').to_string());
-
-        let version = headers
-            .get("x-ais-version")
-            .and_then(|v| v.to_str().ok())
-            .map(|s| s.to_string());
-
-        Ok(ObjectMeta {
-            location: path.clone(),
-            last_modified,
-            size,
-            e_tag,
-            version,
-        })
-    }
-
-    fn parse_content_range(response: &Response) -> Option<Range<u64>> {
-        let content_range = response.headers().get(reqwest::header::CONTENT_RANGE)?;
-        let content_range = content_range.to_str().ok()?;
-
-        let parts: Vec<&str> = content_range.split(' ').collect();
-        if parts.len() != 2 || parts[0] != "bytes" {
-            return None;
-        }
-
-        let range_parts: Vec<&str> = parts[1].split('/').collect();
-        if range_parts.is_empty() {
-            return None;
-        }
-
-        let byte_range: Vec<&str> = range_parts[0].split('-').collect();
-        if byte_range.len() != 2 {
-            return None;
-        }
-
-        let start = byte_range[0].parse::<u64>().ok()?;
-        let end = byte_range[1].parse::<u64>().ok()? + 1;
-
-        Some(start..end)
-    }
-
-    async fn handle_error_response(response: Response) -> AiStoreError {
-        let status = response.status();
-        let body = response.text().await.unwrap_or_default();
-
-        match status {
-            StatusCode::NOT_FOUND => AiStoreError::NotFound { message: body },
-            StatusCode::FORBIDDEN => AiStoreError::Forbidden { message: body },
-            StatusCode::UNAUTHORIZED => AiStoreError::Unauthorized { message: body },
-            StatusCode::CONFLICT => AiStoreError::AlreadyExists { message: body },
-            _ => AiStoreError::Http {
-                status: status.as_u16(),
-                message: body,
-            },
-        }
-    }
-}
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use chrono::Utc;
+use md5::{Digest, Md5};
+use object_store::{
+    path::Path, Attributes, GetOptions, GetResult, ObjectMeta, PutMode, PutOptions, PutPayload,
+    PutResult,
+};
+
+use crate::backend::{self, Backend, ListPage, MetadataHeaders, RequestFactory};
+use crate::bucket::{Bucket, Provider};
+use crate::checksum::{self, ChecksumAlgorithm};
+use crate::error::AiStoreError;
+use crate::request::{ClientExt, HttpClient, HttpRequestBuilder, RequestBody};
+use crate::xml::{
+    self, CompleteMultipartUploadRequest, DeleteError, DeleteObjectsRequest, DeleteResult,
+    ListBucketResult,
+};
+
+#[derive(Debug, Clone)]
+pub(crate) struct S3Config {
+    /// Root of the S3 API, e.g. `{endpoint}/s3`
+    pub url: String,
+    pub bucket: String,
+    pub checksum: Option<ChecksumAlgorithm>,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct S3Client {
+    config: S3Config,
+    client: HttpClient,
+}
+
+impl S3Client {
+    pub(crate) fn new(config: S3Config, client: HttpClient) -> Self {
+        Self { config, client }
+    }
+
+    fn object_url(&self, path: &Path) -> String {
+        format!("{}/{}", self.bucket_url(), backend::encode_key(path))
+    }
+
+    fn bucket_url(&self) -> String {
+        format!("{}/{}", self.config.url, self.config.bucket)
+    }
+
+    /// Send the checksum of `payload` if checksums are enabled
+    fn apply_checksum(
+        &self,
+        request: HttpRequestBuilder,
+        payload: &PutPayload,
+    ) -> Result<HttpRequestBuilder, AiStoreError> {
+        match self.checksum()? {
+            Some(algorithm) => {
+                let digest = checksum::payload_checksum(algorithm, payload);
+                Ok(checksum::apply_checksum(
+                    request,
+                    algorithm,
+                    &digest,
+                    MetadataHeaders::S3,
+                ))
+            }
+            None => Ok(request),
+        }
+    }
+
+    /// The checksum to send with uploads
+    ///
+    /// Fails for algorithms the S3 API has no header for. The builder only
+    /// allows them with the native API, whose multipart uploads come here.
+    fn checksum(&self) -> Result<Option<ChecksumAlgorithm>, AiStoreError> {
+        match self.config.checksum {
+            Some(algorithm) if !algorithm.s3_supported() => Err(AiStoreError::NotSupported {
+                message: format!("{} checksums through the S3 API", algorithm.as_str()),
+            }),
+            checksum => Ok(checksum),
+        }
+    }
+
+    /// Copy `from` in this bucket to `to` in the bucket named `dest`
+    ///
+    /// Only AIS buckets outside any namespace can be reached, see
+    /// [`AiStoreApi::S3`](crate::AiStoreApi::S3).
+    async fn copy_between(
+        &self,
+        from: &Path,
+        dest: &Bucket,
+        to: &Path,
+        mode: PutMode,
+    ) -> Result<(), AiStoreError> {
+        if dest.provider != Provider::Ais || dest.namespace.is_some() {
+            return Err(AiStoreError::NotSupported {
+                message: format!("Copy to {} through the S3 API", dest),
+            });
+        }
+
+        let url = format!(
+            "{}/{}/{}",
+            self.config.url,
+            dest.name,
+            backend::encode_key(to)
+        );
+        let source = format!("{}/{}", self.config.bucket, backend::encode_key(from));
+
+        let request = self
+            .client
+            .put_with_retry(url)
+            .header("x-amz-copy-source", source);
+
+        let response = backend::apply_put_mode(request, &mode)?.send().await?;
+
+        let status = response.status();
+        if !status.is_success() {
+            return Err(backend::put_error_response(to, &mode, response).await);
+        }
+
+        Ok(())
+    }
+}
+
+/// The etag S3 gives an object assembled from `parts`: the MD5 of the parts'
+/// binary MD5s, followed by the number of parts
+///
+/// `None` if a part etag is not a hex MD5.
+fn multipart_etag(parts: &[(u32, String)]) -> Option<String> {
+    let mut hasher = Md5::new();
+    for (_, etag) in parts {
+        let digest = hex::decode(etag.trim_matches('"')).ok()?;
+        if digest.len() != 16 {
+            return None;
+        }
+        hasher.update(&digest);
+    }
+
+    Some(format!(
+        "{}-{}",
+        hex::encode(hasher.finalize()),
+        parts.len()
+    ))
+}
+
+#[async_trait::async_trait]
+impl Backend for S3Client {
+    async fn put_object(
+        &self,
+        path: &Path,
+        payload: PutPayload,
+        opts: PutOptions,
+    ) -> Result<PutResult, AiStoreError> {
+        let url = self.object_url(path);
+        let content_length = payload.content_length();
+
+        let request = backend::apply_put_mode(self.client.put_with_retry(url), &opts.mode)?;
+        let request = backend::apply_attributes(request, &opts.attributes, MetadataHeaders::S3)?;
+        let request = self.apply_checksum(request, &payload)?;
+
+        let response = request
+            .header(
+                reqwest::header::CONTENT_LENGTH.as_str(),
+                content_length.to_string(),
+            )
+            .body(RequestBody::Payload(payload))
+            .send()
+            .await?;
+
+        let status = response.status();
+        if !status.is_success() {
+            return Err(backend::put_error_response(path, &opts.mode, response).await);
+        }
+
+        Ok(backend::put_result(&response))
+    }
+
+    async fn get_object(
+        &self,
+        path: &Path,
+        options: GetOptions,
+    ) -> Result<GetResult, AiStoreError> {
+        let url = self.object_url(path);
+
+        let mut request = if options.head {
+            self.client.head_with_retry(&url)
+        } else {
+            self.client.get_with_retry(&url)
+        };
+
+        // S3 only returns the stored `x-amz-checksum-*` headers when asked to
+        if self.config.checksum.is_some() {
+            request = request.header("x-amz-checksum-mode", "ENABLED");
+        }
+
+        let version: Vec<_> = options
+            .version
+            .iter()
+            .map(|v| ("versionId".to_string(), v.clone()))
+            .collect();
+
+        let response = backend::apply_get_options(request.query_params(version.clone()), &options)
+            .send()
+            .await?;
+
+        let client = self.client.clone();
+        let request: RequestFactory =
+            Arc::new(move || client.get_with_retry(&url).query_params(version.clone()));
+
+        backend::get_result(path, &options, response, request, self.config.checksum).await
+    }
+
+    async fn head_object(&self, path: &Path) -> Result<ObjectMeta, AiStoreError> {
+        let url = self.object_url(path);
+
+        let response = self.client.head_with_retry(url).send().await?;
+
+        let status = response.status();
+        if !status.is_success() {
+            return Err(backend::handle_error_response(path.as_ref(), response).await);
+        }
+
+        backend::extract_object_meta(path, &response)
+    }
+
+    async fn delete_object(&self, path: &Path) -> Result<(), AiStoreError> {
+        let url = self.object_url(path);
+
+        let response = self.client.delete_with_retry(url).send().await?;
+
+        let status = response.status();
+        if !status.is_success() {
+            return Err(backend::handle_error_response(path.as_ref(), response).await);
+        }
+
+        Ok(())
+    }
+
+    async fn list_objects(
+        &self,
+        prefix: Option<&str>,
+        continuation_token: Option<&str>,
+        max_keys: Option<u32>,
+        delimiter: Option<&str>,
+        start_after: Option<&str>,
+    ) -> Result<ListPage, AiStoreError> {
+        let url = self.bucket_url();
+
+        let mut query_params = vec![("list-type".to_string(), "2".to_string())];
+
+        if let Some(prefix) = prefix {
+            query_params.push(("prefix".to_string(), prefix.to_string()));
+        }
+
+        if let Some(token) = continuation_token {
+            query_params.push(("continuation-token".to_string(), token.to_string()));
+        }
+
+        if let Some(max) = max_keys {
+            query_params.push(("max-keys".to_string(), max.to_string()));
+        }
+
+        if let Some(delimiter) = delimiter {
+            query_params.push(("delimiter".to_string(), delimiter.to_string()));
+        }
+
+        if let Some(start_after) = start_after {
+            query_params.push(("start-after".to_string(), start_after.to_string()));
+        }
+
+        let response = self
+            .client
+            .get_with_retry(url)
+            .query_params(query_params)
+            .send()
+            .await?;
+
+        let status = response.status();
+        if !status.is_success() {
+            return Err(backend::handle_error_response(&self.config.bucket, response).await);
+        }
+
+        let body = response
+            .text()
+            .await
+            .map_err(|e| AiStoreError::Request { source: e })?;
+
+        let result: ListBucketResult =
+            xml::from_xml(&body).map_err(|e| AiStoreError::InvalidResponse {
+                message: format!("Failed to parse ListObjectsV2 response: {}", e),
+            })?;
+
+        let is_truncated = result.is_truncated.unwrap_or(false);
+
+        Ok(ListPage {
+            objects: result
+                .contents
+                .into_iter()
+                .filter_map(|entry| {
+                    let location = Path::parse(&entry.key).ok()?;
+                    Some(ObjectMeta {
+                        location,
+                        last_modified: entry.last_modified.unwrap_or_else(Utc::now),
+                        size: entry.size,
+                        e_tag: entry.e_tag,
+                        // ListObjectsV2 carries no versions
+                        version: None,
+                    })
+                })
+                .collect(),
+            common_prefixes: result
+                .common_prefixes
+                .into_iter()
+                .map(|p| p.prefix)
+                .collect(),
+            next_continuation_token: result.next_continuation_token.filter(|_| is_truncated),
+        })
+    }
+
+    async fn delete_object_version(&self, path: &Path, version: &str) -> Result<(), AiStoreError> {
+        let url = self.object_url(path);
+
+        let response = self
+            .client
+            .delete_with_retry(url)
+            .query("versionId", version)
+            .send()
+            .await?;
+
+        let status = response.status();
+        if !status.is_success() {
+            return Err(backend::handle_error_response(path.as_ref(), response).await);
+        }
+
+        Ok(())
+    }
+
+    async fn delete_objects(
+        &self,
+        paths: Vec<Path>,
+    ) -> Result<Vec<Result<Path, AiStoreError>>, AiStoreError> {
+        let url = format!("{}?delete", self.bucket_url());
+
+        let request_body = DeleteObjectsRequest::new(paths.iter().map(|p| p.to_string()));
+        let xml = xml::to_xml(&request_body).map_err(|e| AiStoreError::InvalidResponse {
+            message: format!("Failed to serialize DeleteObjects request: {}", e),
+        })?;
+
+        // Deleting the same keys again changes nothing
+        let response = self
+            .client
+            .post_with_retry(url)
+            .idempotent(true)
+            .header(reqwest::header::CONTENT_TYPE.to_string(), "application/xml")
+            .body(RequestBody::Text(xml))
+            .send()
+            .await?;
+
+        let status = response.status();
+        if !status.is_success() {
+            return Err(backend::handle_error_response(&self.config.bucket, response).await);
+        }
+
+        let body = response
+            .text()
+            .await
+            .map_err(|e| AiStoreError::Request { source: e })?;
+
+        let result: DeleteResult =
+            xml::from_xml(&body).map_err(|e| AiStoreError::InvalidResponse {
+                message: format!("Failed to parse DeleteObjects response: {}", e),
+            })?;
+
+        // Like a single DELETE, removing a missing key is not an error
+        let mut errors: HashMap<String, DeleteError> = result
+            .errors
+            .into_iter()
+            .filter(|e| e.code != "NoSuchKey")
+            .map(|e| (e.key.clone(), e))
+            .collect();
+
+        Ok(paths
+            .into_iter()
+            .map(|path| match errors.remove(path.as_ref()) {
+                Some(error) => Err(AiStoreError::DeleteFailed {
+                    path,
+                    code: error.code,
+                    message: error.message,
+                }),
+                None => Ok(path),
+            })
+            .collect())
+    }
+
+    async fn copy_object(
+        &self,
+        from: &Path,
+        to: &Path,
+        if_not_exists: bool,
+    ) -> Result<(), AiStoreError> {
+        let mode = if if_not_exists {
+            PutMode::Create
+        } else {
+            PutMode::Overwrite
+        };
+
+        self.copy_between(from, &Bucket::new(&self.config.bucket), to, mode)
+            .await
+    }
+
+    async fn copy_object_to(
+        &self,
+        from: &Path,
+        dest: &Bucket,
+        to: &Path,
+    ) -> Result<(), AiStoreError> {
+        self.copy_between(from, dest, to, PutMode::Overwrite).await
+    }
+
+    async fn initiate_multipart_upload(
+        &self,
+        path: &Path,
+        attributes: &Attributes,
+    ) -> Result<String, AiStoreError> {
+        // Fail before anything is uploaded if the parts cannot be checksummed
+        self.checksum()?;
+
+        let url = format!("{}?uploads", self.object_url(path));
+
+        let request = backend::apply_attributes(
+            self.client.post_with_retry(url),
+            attributes,
+            MetadataHeaders::S3,
+        )?;
+
+        let response = request.send().await?;
+
+        let status = response.status();
+        if !status.is_success() {
+            return Err(backend::handle_error_response(path.as_ref(), response).await);
+        }
+
+        if let Some(upload_id) = response.headers().get("x-ais-upload-id") {
+            return upload_id.to_str().map(|s| s.to_string()).map_err(|_| {
+                AiStoreError::InvalidResponse {
+                    message: "Invalid upload ID header".to_string(),
+                }
+            });
+        }
+
+        let body = response
+            .text()
+            .await
+            .map_err(|e| AiStoreError::Request { source: e })?;
+
+        let result: xml::InitiateMultipartUploadResult =
+            xml::from_xml(&body).map_err(|e| AiStoreError::InvalidResponse {
+                message: format!("Failed to parse InitiateMultipartUpload response: {}", e),
+            })?;
+
+        Ok(result.upload_id)
+    }
+
+    async fn upload_part(
+        &self,
+        path: &Path,
+        upload_id: &str,
+        part_number: u32,
+        data: PutPayload,
+    ) -> Result<String, AiStoreError> {
+        let url = format!(
+            "{}?partNumber={}&uploadId={}",
+            self.object_url(path),
+            part_number,
+            backend::encode_query(upload_id)
+        );
+
+        let content_length = data.content_length();
+
+        let response = self
+            .apply_checksum(self.client.put_with_retry(url), &data)?
+            .header(
+                reqwest::header::CONTENT_LENGTH.as_str(),
+                content_length.to_string(),
+            )
+            .body(RequestBody::Payload(data))
+            .send()
+            .await?;
+
+        let status = response.status();
+        if !status.is_success() {
+            return Err(backend::handle_error_response(path.as_ref(), response).await);
+        }
+
+        let etag = response
+            .headers()
+            .get(reqwest::header::ETAG)
+            .and_then(|v| v.to_str().ok())
+            .map(|s| s.trim_matches('"').to_string())
+            .ok_or_else(|| AiStoreError::InvalidResponse {
+                message: "No ETag in part upload response".to_string(),
+            })?;
+
+        Ok(etag)
+    }
+
+    async fn complete_multipart_upload(
+        &self,
+        path: &Path,
+        upload_id: &str,
+        parts: Vec<(u32, String)>,
+    ) -> Result<PutResult, AiStoreError> {
+        let url = format!(
+            "{}?uploadId={}",
+            self.object_url(path),
+            backend::encode_query(upload_id)
+        );
+
+        let expected_etag = multipart_etag(&parts);
+
+        let request_body = CompleteMultipartUploadRequest::new(parts);
+        let xml = xml::to_xml(&request_body).map_err(|e| AiStoreError::InvalidResponse {
+            message: format!("Failed to serialize CompleteMultipartUpload request: {}", e),
+        })?;
+
+        let result = async {
+            let response = self
+                .client
+                .post_with_retry(url)
+                .header(reqwest::header::CONTENT_TYPE.to_string(), "application/xml")
+                .body(RequestBody::Text(xml))
+                .send()
+                .await?;
+
+            let status = response.status();
+            if !status.is_success() {
+                return Err(backend::handle_error_response(path.as_ref(), response).await);
+            }
+
+            Ok(backend::put_result(&response))
+        }
+        .await;
+
+        // The upload may have been completed even though the response was lost
+        // or an error came back, in which case the object already has the etag
+        // of the assembled parts
+        match result {
+            Err(e) => match expected_etag {
+                Some(expected) => match self.head_object(path).await {
+                    Ok(meta) if meta.e_tag.as_deref() == Some(expected.as_str()) => Ok(PutResult {
+                        e_tag: meta.e_tag,
+                        version: meta.version,
+                    }),
+                    _ => Err(e),
+                },
+                None => Err(e),
+            },
+            result => result,
+        }
+    }
+
+    async fn abort_multipart_upload(
+        &self,
+        path: &Path,
+        upload_id: &str,
+    ) -> Result<(), AiStoreError> {
+        let url = format!(
+            "{}?uploadId={}",
+            self.object_url(path),
+            backend::encode_query(upload_id)
+        );
+
+        let response = self.client.delete_with_retry(url).send().await?;
+
+        let status = response.status();
+        if !status.is_success() {
+            return Err(backend::handle_error_response(path.as_ref(), response).await);
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{MockServer, Reply};
+    use crate::request::RequestPolicy;
+
+    /// A key with characters that are reserved or invalid in a URL
+    const KEY: &str = "dir/a?b#c%d e/ünï";
+    const ENCODED_KEY: &str = "dir/a%3Fb%23c%25d%20e/%C3%BCn%C3%AF";
+
+    const UPLOAD_ID: &str = "2~a/b+c=d&e f";
+    const ENCODED_UPLOAD_ID: &str = "2~a%2Fb%2Bc%3Dd%26e%20f";
+
+    fn client(server: &MockServer) -> S3Client {
+        checksum_client(server, None)
+    }
+
+    fn checksum_client(server: &MockServer, checksum: Option<ChecksumAlgorithm>) -> S3Client {
+        let config = S3Config {
+            url: format!("{}/s3", server.url()),
+            bucket: "bucket".to_string(),
+            checksum,
+        };
+        let client = HttpClient::new(reqwest::Client::new(), None, RequestPolicy::default(), None);
+        S3Client::new(config, client)
+    }
+
+    #[tokio::test]
+    async fn keys_and_upload_ids_are_percent_encoded() {
+        let mut server = MockServer::bind().await;
+        server.serve(|_| Reply::status(200).header("etag", "\"0a1b\""));
+        let client = client(&server);
+        let key = Path::parse(KEY).unwrap();
+
+        client
+            .put_object(
+                &key,
+                PutPayload::from_static(b"data"),
+                PutOptions::default(),
+            )
+            .await
+            .unwrap();
+        client
+            .copy_object(&key, &Path::parse("copy/é?").unwrap(), false)
+            .await
+            .unwrap();
+        client
+            .upload_part(&key, UPLOAD_ID, 1, PutPayload::from_static(b"part"))
+            .await
+            .unwrap();
+        client
+            .abort_multipart_upload(&key, UPLOAD_ID)
+            .await
+            .unwrap();
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 4);
+
+        assert_eq!(requests[0].method, "PUT");
+        assert_eq!(requests[0].target, format!("/s3/bucket/{ENCODED_KEY}"));
+
+        assert_eq!(requests[1].target, "/s3/bucket/copy/%C3%A9%3F");
+        assert_eq!(
+            requests[1].header("x-amz-copy-source"),
+            Some(format!("bucket/{ENCODED_KEY}").as_str())
+        );
+
+        assert_eq!(
+            requests[2].target,
+            format!("/s3/bucket/{ENCODED_KEY}?partNumber=1&uploadId={ENCODED_UPLOAD_ID}")
+        );
+
+        assert_eq!(requests[3].method, "DELETE");
+        assert_eq!(
+            requests[3].target,
+            format!("/s3/bucket/{ENCODED_KEY}?uploadId={ENCODED_UPLOAD_ID}")
+        );
+    }
+
+    #[tokio::test]
+    async fn interrupted_reads_resume_where_they_stopped() {
+        let mut server = MockServer::bind().await;
+        server.serve(|request| match request.header("range") {
+            None => Reply::status(200)
+                .header("etag", "\"e1\"")
+                .body("01234")
+                .cut_off_at(10),
+            Some(_) => Reply::status(206)
+                .header("etag", "\"e1\"")
+                .header("content-range", "bytes 5-9/10")
+                .body("56789"),
+        });
+        let client = client(&server);
+
+        let bytes = client
+            .get_object(&Path::from("key"), GetOptions::default())
+            .await
+            .unwrap()
+            .bytes()
+            .await
+            .unwrap();
+        assert_eq!(bytes.as_ref(), b"0123456789");
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 2);
+        assert!(requests[1].header("range").unwrap().starts_with("bytes=5-"));
+        assert_eq!(requests[1].header("if-match"), Some("\"e1\""));
+    }
+
+    #[tokio::test]
+    async fn reads_without_a_length_resume_to_the_end() {
+        let mut server = MockServer::bind().await;
+        server.serve(|request| match request.header("range") {
+            None => Reply::status(200)
+                .header("etag", "\"e1\"")
+                .body("01234")
+                .cut_off_unannounced(),
+            Some(_) => Reply::status(206)
+                .header("etag", "\"e1\"")
+                .header("content-range", "bytes 5-9/10")
+                .body("56789"),
+        });
+        let client = client(&server);
+
+        let bytes = client
+            .get_object(&Path::from("key"), GetOptions::default())
+            .await
+            .unwrap()
+            .bytes()
+            .await
+            .unwrap();
+        assert_eq!(bytes.as_ref(), b"0123456789");
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[1].header("range"), Some("bytes=5-"));
+        assert_eq!(requests[1].header("if-match"), Some("\"e1\""));
+    }
+
+    #[tokio::test]
+    async fn resumes_that_restart_elsewhere_fail() {
+        let mut server = MockServer::bind().await;
+        server.serve(|request| match request.header("range") {
+            None => Reply::status(200)
+                .header("etag", "\"e1\"")
+                .body("01234")
+                .cut_off_at(10),
+            Some(_) => Reply::status(206)
+                .header("etag", "\"e1\"")
+                .header("content-range", "bytes 0-9/10")
+                .body("0123456789"),
+        });
+        let client = client(&server);
+
+        let result = client
+            .get_object(&Path::from("key"), GetOptions::default())
+            .await
+            .unwrap()
+            .bytes()
+            .await;
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    async fn conditional_puts_are_not_retried() {
+        let mut server = MockServer::bind().await;
+        server.serve(|_| Reply::status(500));
+        let client = client(&server);
+        let key = Path::from("key");
+
+        let create = PutOptions::from(PutMode::Create);
+        client
+            .put_object(&key, PutPayload::from_static(b"data"), create)
+            .await
+            .unwrap_err();
+        assert_eq!(server.requests().len(), 1);
+
+        client
+            .put_object(
+                &key,
+                PutPayload::from_static(b"data"),
+                PutOptions::default(),
+            )
+            .await
+            .unwrap_err();
+        assert_eq!(server.requests().len(), 1 + 4);
+    }
+
+    #[tokio::test]
+    async fn put_modes_are_sent_as_conditions() {
+        let mut server = MockServer::bind().await;
+        server.serve(|request| match request.target.as_str() {
+            "/s3/bucket/changed" => Reply::status(412),
+            "/s3/bucket/busy" => Reply::status(409),
+            _ => Reply::status(200).header("etag", "\"e2\""),
+        });
+        let client = client(&server);
+        let update = |e_tag: Option<&str>| {
+            PutOptions::from(PutMode::Update(object_store::UpdateVersion {
+                e_tag: e_tag.map(str::to_string),
+                version: Some("v1".to_string()),
+            }))
+        };
+        let put = |key: &'static str, opts: PutOptions| {
+            let client = &client;
+            async move {
+                let payload = PutPayload::from_static(b"data");
+                client.put_object(&Path::from(key), payload, opts).await
+            }
+        };
+
+        put("new", PutOptions::from(PutMode::Create)).await.unwrap();
+        put("new", update(Some("e1"))).await.unwrap();
+
+        let requests = server.requests();
+        assert_eq!(requests[0].header("if-none-match"), Some("*"));
+        assert_eq!(requests[0].header("if-match"), None);
+        assert_eq!(requests[1].header("if-match"), Some("e1"));
+        assert_eq!(requests[1].header("if-none-match"), None);
+
+        // A failed condition means the object exists, or was changed
+        for key in ["changed", "busy"] {
+            let err = put(key, PutOptions::from(PutMode::Create))
+                .await
+                .unwrap_err();
+            let err = object_store::Error::from(err);
+            assert!(
+                matches!(err, object_store::Error::AlreadyExists { .. }),
+                "{key}: {err}"
+            );
+
+            let err = put(key, update(Some("e1"))).await.unwrap_err();
+            let err = object_store::Error::from(err);
+            assert!(
+                matches!(err, object_store::Error::Precondition { .. }),
+                "{key}: {err}"
+            );
+        }
+
+        // `If-Match` compares etags, so a version alone cannot be checked
+        let sent = server.requests().len();
+        let err = put("new", update(None)).await.unwrap_err();
+        assert!(matches!(err, AiStoreError::Configuration { .. }), "{err}");
+        assert_eq!(server.requests().len(), sent);
+    }
+
+    #[tokio::test]
+    async fn copies_to_qualified_buckets_are_rejected() {
+        let mut server = MockServer::bind().await;
+        server.serve(|_| Reply::status(200));
+        let client = client(&server);
+        let key = Path::from("key");
+
+        let cloud = Bucket {
+            provider: Provider::Gcp,
+            ..Bucket::new("dst")
+        };
+        let namespaced = Bucket {
+            namespace: Some("@remote#ns".parse().unwrap()),
+            ..Bucket::new("dst")
+        };
+        for dest in [cloud, namespaced] {
+            let err = client.copy_object_to(&key, &dest, &key).await.unwrap_err();
+            assert!(matches!(err, AiStoreError::NotSupported { .. }), "{err}");
+        }
+        assert!(server.requests().is_empty());
+
+        client
+            .copy_object_to(&key, &Bucket::new("dst"), &key)
+            .await
+            .unwrap();
+        assert_eq!(server.requests()[0].target, "/s3/dst/key");
+    }
+
+    #[tokio::test]
+    async fn uploads_carry_the_checksum() {
+        let mut server = MockServer::bind().await;
+        server.serve(|_| Reply::status(200).header("etag", "\"0a1b\""));
+        let client = checksum_client(&server, Some(ChecksumAlgorithm::Crc32c));
+        let key = Path::from("key");
+
+        client
+            .put_object(
+                &key,
+                PutPayload::from_static(b"data"),
+                PutOptions::default(),
+            )
+            .await
+            .unwrap();
+        client
+            .upload_part(&key, "id", 1, PutPayload::from_static(b"data"))
+            .await
+            .unwrap();
+
+        // CRC32C of "data" is 0xaed87dd1
+        for request in server.requests() {
+            assert_eq!(request.header("x-amz-checksum-crc32c"), Some("rth90Q=="));
+        }
+    }
+
+    #[tokio::test]
+    async fn checksums_without_an_s3_header_fail_multipart_uploads() {
+        let mut server = MockServer::bind().await;
+        server.serve(|_| Reply::status(200).header("etag", "\"0a1b\""));
+        let client = checksum_client(&server, Some(ChecksumAlgorithm::Xxhash));
+        let key = Path::from("key");
+
+        let err = client
+            .initiate_multipart_upload(&key, &Attributes::new())
+            .await
+            .unwrap_err();
+        assert!(matches!(err, AiStoreError::NotSupported { .. }), "{err}");
+        let err = client
+            .upload_part(&key, "id", 1, PutPayload::from_static(b"data"))
+            .await
+            .unwrap_err();
+        assert!(matches!(err, AiStoreError::NotSupported { .. }), "{err}");
+        assert!(server.requests().is_empty());
+    }
+
+    /// Etags of the parts "a" and "b", and of the object assembled from them
+    const PART_ETAGS: [&str; 2] = [
+        "0cc175b9c0f1b6a831c399e269772661",
+        "92eb5ffee6ae2fec3ad71c777531578f",
+    ];
+    const MULTIPART_ETAG: &str = "96e024ba2074fe77e8e965ba43a704be-2";
+
+    fn parts() -> Vec<(u32, String)> {
+        vec![
+            (1, PART_ETAGS[0].to_string()),
+            (2, format!("\"{}\"", PART_ETAGS[1])),
+        ]
+    }
+
+    #[test]
+    fn multipart_etags() {
+        assert_eq!(multipart_etag(&parts()).as_deref(), Some(MULTIPART_ETAG));
+        assert_eq!(multipart_etag(&[(1, "not-md5".to_string())]), None);
+        assert_eq!(multipart_etag(&[(1, "0a1b".to_string())]), None);
+    }
+
+    /// A server that fails every completion and reports `e_tag` on HEAD
+    async fn failing_completion(e_tag: &'static str) -> MockServer {
+        let mut server = MockServer::bind().await;
+        server.serve(move |request| match request.method.as_str() {
+            "HEAD" => Reply::status(200).header("etag", format!("\"{e_tag}\"")),
+            _ => Reply::status(500),
+        });
+        server
+    }
+
+    #[tokio::test]
+    async fn failed_completions_are_checked_with_a_head() {
+        let server = failing_completion(MULTIPART_ETAG).await;
+        let result = client(&server)
+            .complete_multipart_upload(&Path::from("key"), "id", parts())
+            .await
+            .unwrap();
+        assert_eq!(result.e_tag.as_deref(), Some(MULTIPART_ETAG));
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[0].method, "POST");
+        assert_eq!(requests[1].method, "HEAD");
+
+        // Another object under the key means the upload did not complete
+        let server = failing_completion("0a1b-2").await;
+        let err = client(&server)
+            .complete_multipart_upload(&Path::from("key"), "id", parts())
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(err, AiStoreError::Http { status: 500, .. }),
+            "{err}"
+        );
+    }
+}
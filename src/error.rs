@@ -1,21 +1,64 @@
 use object_store::path::Path;
 
+/// What the cluster said about a failed request
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ErrorDetails {
+    /// Error code from the response body, e.g. `SlowDown` or `NoSuchBucket`
+    pub code: Option<String>,
+    pub message: String,
+    pub request_id: Option<String>,
+    /// Bucket or object the error refers to
+    pub resource: Option<String>,
+}
+
+impl ErrorDetails {
+    pub(crate) fn new(message: impl Into<String>) -> Self {
+        Self {
+            message: message.into(),
+            ..Default::default()
+        }
+    }
+}
+
+impl std::fmt::Display for ErrorDetails {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.message)?;
+        if let Some(code) = &self.code {
+            write!(f, " ({code})")?;
+        }
+        Ok(())
+    }
+}
+
 #[derive(Debug, thiserror::Error)]
 pub enum AiStoreError {
-    #[error("Object not found: {message}")]
-    NotFound { message: String },
+    /// `path` is the object, or the bucket for requests about a whole bucket
+    #[error("Object not found: {path}: {details}")]
+    NotFound { path: String, details: ErrorDetails },
+
+    #[error("Access forbidden: {path}: {details}")]
+    Forbidden { path: String, details: ErrorDetails },
 
-    #[error("Access forbidden: {message}")]
-    Forbidden { message: String },
+    #[error("Unauthorized: {path}: {details}")]
+    Unauthorized { path: String, details: ErrorDetails },
 
-    #[error("Unauthorized: {message}")]
-    Unauthorized { message: String },
+    #[error("Object already exists: {path}: {details}")]
+    AlreadyExists { path: String, details: ErrorDetails },
 
-    #[error("Object already exists: {message}")]
-    AlreadyExists { message: String },
+    #[error("HTTP error {status}: {details}")]
+    Http {
+        status: u16,
+        details: ErrorDetails,
+        /// Whether the request may succeed if sent again, e.g. after throttling
+        retryable: bool,
+    },
 
-    #[error("HTTP error {status}: {message}")]
-    Http { status: u16, message: String },
+    #[error("Failed to delete {path}: {code} {message}")]
+    DeleteFailed {
+        path: Path,
+        code: String,
+        message: String,
+    },
 
     #[error("Request error: {source}")]
     Request {
@@ -34,17 +77,27 @@ pub enum AiStoreError {
 
     #[error("Configuration error: {message}")]
     Configuration { message: String },
+
+    #[error("Checksum mismatch for {path}: expected {expected}, got {actual}")]
+    ChecksumMismatch {
+        path: Path,
+        expected: String,
+        actual: String,
+    },
+
+    #[error("Not supported: {message}")]
+    NotSupported { message: String },
 }
 
 impl From<AiStoreError> for object_store::Error {
     fn from(err: AiStoreError) -> Self {
         match &err {
-            AiStoreError::NotFound { message } => object_store::Error::NotFound {
-                path: message.clone(),
+            AiStoreError::NotFound { path, .. } => object_store::Error::NotFound {
+                path: path.clone(),
                 source: Box::new(err),
             },
-            AiStoreError::AlreadyExists { message } => object_store::Error::AlreadyExists {
-                path: message.clone(),
+            AiStoreError::AlreadyExists { path, .. } => object_store::Error::AlreadyExists {
+                path: path.clone(),
                 source: Box::new(err),
             },
             AiStoreError::NotModified { path } => object_store::Error::NotModified {
@@ -55,6 +108,17 @@ impl From<AiStoreError> for object_store::Error {
                 path: path.to_string(),
                 source: Box::new(err),
             },
+            AiStoreError::Forbidden { path, .. } => object_store::Error::PermissionDenied {
+                path: path.clone(),
+                source: Box::new(err),
+            },
+            AiStoreError::Unauthorized { path, .. } => object_store::Error::Unauthenticated {
+                path: path.clone(),
+                source: Box::new(err),
+            },
+            AiStoreError::NotSupported { .. } => object_store::Error::NotSupported {
+                source: Box::new(err),
+            },
             _ => object_store::Error::Generic {
                 store: "aistore",
                 source: Box::new(err),
@@ -0,0 +1,134 @@
+//! JSON request and response types for the native AIStore API
+
+use serde::{Deserialize, Serialize};
+
+/// Control message sent in the body of native API requests
+#[derive(Debug, Serialize)]
+pub struct ActionMessage<T> {
+    pub action: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub value: Option<T>,
+}
+
+impl<T> ActionMessage<T> {
+    pub fn new(action: &'static str, value: T) -> Self {
+        Self {
+            action,
+            name: None,
+            value: Some(value),
+        }
+    }
+}
+
+impl ActionMessage<()> {
+    /// A message that carries only a name, e.g. the new name of a `rename-obj`
+    pub fn named(action: &'static str, name: impl Into<String>) -> Self {
+        Self {
+            action,
+            name: Some(name.into()),
+            value: None,
+        }
+    }
+}
+
+/// A bucket as the native API spells it in message bodies
+#[derive(Debug, Serialize)]
+pub struct BucketMessage {
+    pub name: String,
+    pub provider: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub namespace: Option<NamespaceMessage>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct NamespaceMessage {
+    pub uuid: String,
+    pub name: String,
+}
+
+impl From<&crate::bucket::Bucket> for BucketMessage {
+    fn from(bucket: &crate::bucket::Bucket) -> Self {
+        Self {
+            name: bucket.name.clone(),
+            provider: bucket.provider.as_str(),
+            namespace: bucket.namespace.as_ref().map(|ns| NamespaceMessage {
+                uuid: ns.uuid.clone(),
+                name: ns.name.clone(),
+            }),
+        }
+    }
+}
+
+/// Value of the `copy-listrange` action, which copies objects into another bucket
+#[derive(Debug, Serialize)]
+pub struct CopyObjectsMessage {
+    pub objnames: Vec<String>,
+    /// Prefix added to the name of every copy
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub prepend: String,
+    pub tobck: BucketMessage,
+}
+
+/// Selects the xaction whose status is requested
+#[derive(Debug, Serialize)]
+pub struct XactionQuery {
+    pub id: String,
+}
+
+/// Status of an xaction, the cluster's asynchronous jobs
+#[derive(Debug, Deserialize)]
+pub struct XactionStatus {
+    #[serde(default)]
+    pub err: String,
+    /// Unix time in nanoseconds at which the xaction finished, 0 while it runs
+    #[serde(default)]
+    pub end_time: i64,
+    #[serde(default)]
+    pub aborted: bool,
+}
+
+/// Value of the `list` action
+#[derive(Debug, Default, Serialize)]
+pub struct ListObjectsMessage {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prefix: Option<String>,
+    pub props: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub continuation_token: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pagesize: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub start_after: Option<String>,
+    pub time_format: &'static str,
+}
+
+/// Error body of a failed native API request
+#[derive(Debug, Deserialize)]
+pub struct ErrorResponse {
+    pub message: String,
+    #[serde(default)]
+    pub url_path: Option<String>,
+}
+
+/// Response to the `list` action
+#[derive(Debug, Deserialize)]
+pub struct ListObjectsResult {
+    #[serde(default)]
+    pub entries: Vec<ListEntry>,
+    #[serde(default)]
+    pub continuation_token: String,
+}
+
+/// Object entry in a `list` response
+#[derive(Debug, Deserialize)]
+pub struct ListEntry {
+    pub name: String,
+    #[serde(default)]
+    pub size: u64,
+    #[serde(default)]
+    pub atime: Option<String>,
+    #[serde(default)]
+    pub version: Option<String>,
+}
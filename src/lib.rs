@@ -1,30 +1,71 @@
+mod auth;
+mod backend;
+mod bucket;
 mod builder;
+mod checksum;
 mod client;
 mod error;
+mod json;
+#[cfg(test)]
+mod mock;
 mod multipart;
+mod native;
+mod parse;
 mod request;
 mod xml;
 
+use std::ops::Range;
 use std::sync::Arc;
 
-use chrono::Utc;
-use futures::stream::BoxStream;
-use futures::StreamExt;
+use bytes::Bytes;
+use futures::stream::{BoxStream, TryChunksError};
+use futures::{StreamExt, TryStreamExt};
+use tokio::io::{AsyncSeekExt, AsyncWriteExt};
 
+pub use bucket::{Bucket, Namespace, Provider};
 pub use builder::*;
-pub use error::AiStoreError;
+pub use checksum::ChecksumAlgorithm;
+pub use error::{AiStoreError, ErrorDetails};
+pub use parse::parse_url_opts;
+pub use request::RequestPolicy;
 
+use crate::backend::Backend;
 use crate::multipart::AiStoreMultipartUpload;
 
+/// Maximum number of keys in a single DeleteObjects request
+const DELETE_BATCH_SIZE: usize = 1000;
+
+/// Number of DeleteObjects requests `delete_stream` keeps in flight, and of
+/// single deletes per batch in buckets without DeleteObjects
+const DELETE_CONCURRENCY: usize = 10;
+
+/// An [`ObjectStore`](object_store::ObjectStore) backed by an AIStore bucket
+///
+/// # Object versions
+///
+/// Which versioned operations work depends on the API and the bucket:
+///
+/// | | S3 API | Native API, AIS bucket | Native API, cloud or namespaced bucket |
+/// |---|---|---|---|
+/// | [`GetOptions::version`](object_store::GetOptions::version) | sent as `versionId` | current version only | current version only |
+/// | [`ObjectMeta::version`](object_store::ObjectMeta::version) in listings | never set | set | set |
+/// | [`AiStore::delete_version`] | yes | yes | not supported |
+///
+/// "Current version only" means that asking for any other version fails with
+/// [`object_store::Error::NotFound`].
 #[derive(Debug, Clone)]
 pub struct AiStore {
-    client: Arc<client::S3Client>,
-    bucket_name: String,
+    client: Arc<dyn Backend>,
+    bucket: Bucket,
+    multipart_concurrency: usize,
+    copy_mode: CopyMode,
+    range_coalesce: u64,
+    range_concurrency: usize,
 }
 
 impl std::fmt::Display for AiStore {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "AiStore({})", self.bucket_name)
+        write!(f, "AiStore({})", self.bucket.name)
     }
 }
 
@@ -34,10 +75,10 @@ impl object_store::ObjectStore for AiStore {
         &self,
         location: &object_store::path::Path,
         payload: object_store::PutPayload,
-        _opts: object_store::PutOptions,
+        opts: object_store::PutOptions,
     ) -> object_store::Result<object_store::PutResult> {
         self.client
-            .put_object(location, payload)
+            .put_object(location, payload, opts)
             .await
             .map_err(Into::into)
     }
@@ -45,11 +86,11 @@ impl object_store::ObjectStore for AiStore {
     async fn put_multipart_opts(
         &self,
         location: &object_store::path::Path,
-        _opts: object_store::PutMultipartOptions,
+        opts: object_store::PutMultipartOptions,
     ) -> object_store::Result<Box<dyn object_store::MultipartUpload>> {
         let upload_id = self
             .client
-            .initiate_multipart_upload(location)
+            .initiate_multipart_upload(location, &opts.attributes)
             .await
             .map_err(object_store::Error::from)?;
 
@@ -57,6 +98,7 @@ impl object_store::ObjectStore for AiStore {
             self.client.clone(),
             location.clone(),
             upload_id,
+            self.multipart_concurrency,
         )))
     }
 
@@ -71,6 +113,46 @@ impl object_store::ObjectStore for AiStore {
             .map_err(Into::into)
     }
 
+    /// Ranges less than the configured gap apart are fetched with one request,
+    /// and the merged requests run concurrently. AIStore rejects multi-range
+    /// `Range` headers, so every merged range is a separate GET.
+    async fn get_ranges(
+        &self,
+        location: &object_store::path::Path,
+        ranges: &[Range<u64>],
+    ) -> object_store::Result<Vec<Bytes>> {
+        let fetch_ranges = merge_ranges(ranges, self.range_coalesce);
+
+        let fetched: Vec<Bytes> = futures::stream::iter(fetch_ranges.iter().cloned())
+            .map(|range| self.get_range(location, range))
+            .buffered(self.range_concurrency.max(1))
+            .try_collect()
+            .await?;
+
+        ranges
+            .iter()
+            .map(|range| {
+                let idx = fetch_ranges.partition_point(|r| r.start <= range.start) - 1;
+                let fetch_range = &fetch_ranges[idx];
+                let bytes = &fetched[idx];
+
+                // A short fetch means the object ends there, so ranges past
+                // its end fail like a single `get_range` would
+                let object_end = fetch_range.start + bytes.len() as u64;
+                let range = object_store::GetRange::Bounded(range.clone())
+                    .as_range(object_end)
+                    .map_err(|e| object_store::Error::Generic {
+                        store: "aistore",
+                        source: Box::new(e),
+                    })?;
+
+                let start = (range.start - fetch_range.start) as usize;
+                let end = (range.end - fetch_range.start) as usize;
+                Ok(bytes.slice(start..end))
+            })
+            .collect()
+    }
+
     async fn head(
         &self,
         location: &object_store::path::Path,
@@ -85,88 +167,67 @@ impl object_store::ObjectStore for AiStore {
             .map_err(Into::into)
     }
 
+    fn delete_stream<'a>(
+        &'a self,
+        locations: BoxStream<'a, object_store::Result<object_store::path::Path>>,
+    ) -> BoxStream<'a, object_store::Result<object_store::path::Path>> {
+        locations
+            .try_chunks(DELETE_BATCH_SIZE)
+            .map(move |chunk| async move {
+                // An error in the input cuts a batch short; the paths before it
+                // are still deleted, and the error follows their results
+                let (locations, error) = match chunk {
+                    Ok(locations) => (locations, None),
+                    Err(TryChunksError(locations, e)) => (locations, Some(e)),
+                };
+
+                let results = if locations.is_empty() {
+                    vec![]
+                } else {
+                    match self.client.delete_objects(locations).await {
+                        Ok(results) => results.into_iter().map(|r| r.map_err(Into::into)).collect(),
+                        Err(e) => vec![Err(e.into())],
+                    }
+                };
+
+                futures::stream::iter(results.into_iter().chain(error.map(Err)))
+            })
+            .buffered(DELETE_CONCURRENCY)
+            .flatten()
+            .boxed()
+    }
+
     fn list(
         &self,
         prefix: Option<&object_store::path::Path>,
     ) -> BoxStream<'static, object_store::Result<object_store::ObjectMeta>> {
-        let client = self.client.clone();
-        let prefix = prefix.map(|p| p.to_string());
-
-        futures::stream::unfold(
-            ListState {
-                client,
-                prefix,
-                continuation_token: None,
-                done: false,
-                buffer: vec![],
-            },
-            |mut state| async move {
-                if state.done && state.buffer.is_empty() {
-                    return None;
-                }
-
-                if let Some(item) = state.buffer.pop() {
-                    return Some((Ok(item), state));
-                }
+        self.list_paginated(prefix, None)
+    }
 
-                let result = state
-                    .client
-                    .list_objects(
-                        state.prefix.as_deref(),
-                        state.continuation_token.as_deref(),
-                        Some(1000),
-                    )
-                    .await;
-
-                match result {
-                    Ok(response) => {
-                        let is_truncated = response.is_truncated.unwrap_or(false);
-                        if !is_truncated || response.next_continuation_token.is_none() {
-                            state.done = true;
-                        } else {
-                            state.continuation_token = response.next_continuation_token;
-                        }
+    fn list_with_offset(
+        &self,
+        prefix: Option<&object_store::path::Path>,
+        offset: &object_store::path::Path,
+    ) -> BoxStream<'static, object_store::Result<object_store::ObjectMeta>> {
+        let offset = offset.clone();
 
-                        state.buffer = response
-                            .contents
-                            .into_iter()
-                            .filter_map(|entry| {
-                                let location = object_store::path::Path::parse(&entry.key).ok()?;
-                                Some(object_store::ObjectMeta {
-                                    location,
-                                    last_modified: entry.last_modified.unwrap_or_else(Utc::now),
-                                    size: entry.size,
-                                    e_tag: entry.e_tag,
-                                    version: None,
-                                })
-                            })
-                            .collect();
-
-                        state.buffer.reverse();
-
-                        if let Some(item) = state.buffer.pop() {
-                            Some((Ok(item), state))
-                        } else {
-                            None
-                        }
-                    }
-                    Err(e) => {
-                        state.done = true;
-                        Some((Err(e.into()), state))
-                    }
-                }
-            },
-        )
-        .boxed()
+        // The server skips to the offset; the filter only guards against servers
+        // that ignore `start-after`
+        self.list_paginated(prefix, Some(offset.to_string()))
+            .try_filter(move |meta| futures::future::ready(meta.location > offset))
+            .boxed()
     }
 
     async fn list_with_delimiter(
         &self,
         prefix: Option<&object_store::path::Path>,
     ) -> object_store::Result<object_store::ListResult> {
-        // AIStore doesn't have native delimiter support in the same way as S3
-        // We simulate it by listing all objects and grouping them
-        let prefix_str = prefix.map(|p| p.to_string()).unwrap_or_default();
+        // Ask the server to group by `/`; servers that ignore the delimiter (and the
+        // native API) return the whole subtree, which is grouped here instead
+        let prefix = match prefix {
+            Some(p) if !p.as_ref().is_empty() => format!("{p}/"),
+            _ => String::new(),
+        };
 
         let mut objects = vec![];
         let mut common_prefixes = std::collections::HashSet::new();
@@ -175,48 +236,35 @@ impl object_store::ObjectStore for AiStore {
         loop {
             let response = self
                 .client
-                .list_objects(Some(&prefix_str), continuation_token.as_deref(), Some(1000))
+                .list_objects(
+                    Some(&prefix),
+                    continuation_token.as_deref(),
+                    Some(1000),
+                    Some("/"),
+                    None,
+                )
                 .await
                 .map_err(object_store::Error::from)?;
 
-            for entry in response.contents {
-                let name = &entry.key;
+            common_prefixes.extend(response.common_prefixes);
 
-                let relative_path = if prefix_str.is_empty() {
-                    name.as_str()
-                } else if let Some(stripped) = name.strip_prefix(&prefix_str) {
-                    stripped.trim_start_matches('/')
-                } else {
+            for entry in response.objects {
+                let Some(relative_path) = entry.location.as_ref().strip_prefix(&prefix) else {
                     continue;
                 };
 
-                if let Some(slash_pos) = relative_path.find('/') {
-                    let dir_prefix = if prefix_str.is_empty() {
-                        format!("{}/", &relative_path[..slash_pos])
-                    } else {
-                        format!(
-                            "{}/{}/",
-                            prefix_str.trim_end_matches('/'),
-                            &relative_path[..slash_pos]
-                        )
-                    };
-                    common_prefixes.insert(dir_prefix);
-                } else if let Ok(location) = object_store::path::Path::parse(&entry.key) {
-                    objects.push(object_store::ObjectMeta {
-                        location,
-                        last_modified: entry.last_modified.unwrap_or_else(Utc::now),
-                        size: entry.size,
-                        e_tag: entry.e_tag,
-                        version: None,
-                    });
+                match relative_path.split_once('/') {
+                    Some((dir, _)) => {
+                        common_prefixes.insert(format!("{prefix}{dir}/"));
+                    }
+                    None => objects.push(entry),
                 }
             }
 
-            let is_truncated = response.is_truncated.unwrap_or(false);
-            if !is_truncated || response.next_continuation_token.is_none() {
-                break;
+            match response.next_continuation_token {
+                Some(token) => continuation_token = Some(token),
+                None => break,
             }
-            continuation_token = response.next_continuation_token;
         }
 
         Ok(object_store::ListResult {
@@ -233,7 +281,47 @@ impl object_store::ObjectStore for AiStore {
         from: &object_store::path::Path,
         to: &object_store::path::Path,
     ) -> object_store::Result<()> {
-        self.client.copy_object(from, to).await.map_err(Into::into)
+        self.client
+            .copy_object(from, to, false)
+            .await
+            .map_err(Into::into)
+    }
+
+    async fn rename(
+        &self,
+        from: &object_store::path::Path,
+        to: &object_store::path::Path,
+    ) -> object_store::Result<()> {
+        match self.client.rename_object(from, to).await {
+            Err(AiStoreError::NotSupported { .. }) => {
+                self.copy(from, to).await?;
+                self.delete(from).await
+            }
+            result => result.map_err(Into::into),
+        }
+    }
+
+    async fn rename_if_not_exists(
+        &self,
+        from: &object_store::path::Path,
+        to: &object_store::path::Path,
+    ) -> object_store::Result<()> {
+        // The native rename has no precondition, so the destination is
+        // checked with a HEAD first in every copy mode
+        self.head_absent(to).await?;
+
+        match self.client.rename_object(from, to).await {
+            Err(AiStoreError::NotSupported { .. }) => {
+                // The HEAD above already covers `HeadThenCopy`
+                let if_not_exists = self.copy_mode == CopyMode::Conditional;
+                self.client
+                    .copy_object(from, to, if_not_exists)
+                    .await
+                    .map_err(object_store::Error::from)?;
+                self.delete(from).await
+            }
+            result => result.map_err(Into::into),
+        }
     }
 
     async fn copy_if_not_exists(
@@ -241,27 +329,1215 @@ impl object_store::ObjectStore for AiStore {
         from: &object_store::path::Path,
         to: &object_store::path::Path,
     ) -> object_store::Result<()> {
-        // Check if destination exists first
-        match self.head(to).await {
+        match self.copy_mode {
+            CopyMode::Conditional => self
+                .client
+                .copy_object(from, to, true)
+                .await
+                .map_err(Into::into),
+            CopyMode::HeadThenCopy => {
+                self.head_absent(to).await?;
+                self.copy(from, to).await
+            }
+        }
+    }
+}
+
+impl AiStore {
+    /// The bucket this store reads and writes
+    pub fn bucket(&self) -> &Bucket {
+        &self.bucket
+    }
+
+    /// Copy `from` in this store's bucket to `to` in another bucket of the
+    /// same cluster, without moving the data through the client
+    ///
+    /// `dest` is a [`Bucket`] or another [`AiStore`]. With the S3 API, only AIS
+    /// buckets outside a namespace can be copied to, see [`AiStoreApi::S3`].
+    /// With the native API, cloud, remote and namespaced buckets are copied
+    /// between with the `copy-listrange` action, which keeps the object name:
+    /// `to` must then be `from`, optionally with a prefix in front.
+    pub async fn copy_to(
+        &self,
+        dest: impl Into<Bucket>,
+        from: &object_store::path::Path,
+        to: &object_store::path::Path,
+    ) -> object_store::Result<()> {
+        self.client
+            .copy_object_to(from, &dest.into(), to)
+            .await
+            .map_err(Into::into)
+    }
+
+    /// Read an object with `concurrency` ranged GETs of `chunk_size` bytes in
+    /// flight, yielding the chunks in order
+    ///
+    /// Every chunk is pinned with `If-Match` to the etag returned by the initial
+    /// HEAD, so a concurrent overwrite fails the read instead of mixing versions.
+    pub async fn get_parallel(
+        &self,
+        location: &object_store::path::Path,
+        chunk_size: u64,
+        concurrency: usize,
+    ) -> object_store::Result<BoxStream<'static, object_store::Result<Bytes>>> {
+        let meta = self.client.head_object(location).await?;
+        let ranges = chunk_ranges(meta.size, chunk_size)?;
+
+        let client = self.client.clone();
+        let location = location.clone();
+
+        Ok(futures::stream::iter(ranges)
+            .map(move |range| {
+                fetch_chunk(client.clone(), location.clone(), range, meta.e_tag.clone())
+            })
+            .buffered(concurrency.max(1))
+            .boxed())
+    }
+
+    /// Download an object into the file at `path` with parallel ranged GETs,
+    /// like [`AiStore::get_parallel`]
+    ///
+    /// Chunks are written at their offsets as they arrive. The file is created
+    /// or truncated first. Returns the number of bytes written.
+    pub async fn download_to_file(
+        &self,
+        location: &object_store::path::Path,
+        path: impl AsRef<std::path::Path>,
+        chunk_size: u64,
+        concurrency: usize,
+    ) -> object_store::Result<u64> {
+        let meta = self.client.head_object(location).await?;
+        let ranges = chunk_ranges(meta.size, chunk_size)?;
+        let path = path.as_ref();
+
+        let mut file = tokio::fs::File::create(path).await.map_err(io_error)?;
+        file.set_len(meta.size).await.map_err(io_error)?;
+
+        let mut chunks = futures::stream::iter(ranges)
+            .map(|range| {
+                let offset = range.start;
+                let chunk = fetch_chunk(
+                    self.client.clone(),
+                    location.clone(),
+                    range,
+                    meta.e_tag.clone(),
+                );
+                async move { Ok::<_, object_store::Error>((offset, chunk.await?)) }
+            })
+            .buffer_unordered(concurrency.max(1));
+
+        while let Some((offset, bytes)) = chunks.try_next().await? {
+            file.seek(std::io::SeekFrom::Start(offset))
+                .await
+                .map_err(io_error)?;
+            file.write_all(&bytes).await.map_err(io_error)?;
+        }
+        file.flush().await.map_err(io_error)?;
+
+        Ok(meta.size)
+    }
+
+    /// Delete a single version of an object in a versioned bucket, as reported
+    /// in [`ObjectMeta::version`](object_store::ObjectMeta::version)
+    ///
+    /// Not supported for cloud or namespaced buckets through the native API;
+    /// see [Object versions](AiStore#object-versions).
+    pub async fn delete_version(
+        &self,
+        location: &object_store::path::Path,
+        version: &str,
+    ) -> object_store::Result<()> {
+        self.client
+            .delete_object_version(location, version)
+            .await
+            .map_err(Into::into)
+    }
+
+    /// Fail with `AlreadyExists` if `location` exists
+    ///
+    /// Racy: the object may be created between this check and the next request.
+    async fn head_absent(&self, location: &object_store::path::Path) -> object_store::Result<()> {
+        match object_store::ObjectStore::head(self, location).await {
             Ok(_) => Err(object_store::Error::AlreadyExists {
-                path: to.to_string(),
+                path: location.to_string(),
                 source: Box::new(AiStoreError::AlreadyExists {
-                    message: format!("Object already exists: {}", to),
+                    path: location.to_string(),
+                    details: ErrorDetails::new("Found by HEAD"),
                 }),
             }),
-            Err(object_store::Error::NotFound { .. }) => {
-                // Object doesn't exist, proceed with copy
-                self.copy(from, to).await
-            }
+            Err(object_store::Error::NotFound { .. }) => Ok(()),
             Err(e) => Err(e),
         }
     }
+
+    /// List recursively, page by page, starting after `start_after` if given
+    fn list_paginated(
+        &self,
+        prefix: Option<&object_store::path::Path>,
+        start_after: Option<String>,
+    ) -> BoxStream<'static, object_store::Result<object_store::ObjectMeta>> {
+        let client = self.client.clone();
+        let prefix = prefix.map(|p| p.to_string());
+
+        futures::stream::unfold(
+            ListState {
+                client,
+                prefix,
+                start_after,
+                continuation_token: None,
+                done: false,
+                buffer: vec![],
+            },
+            |mut state| async move {
+                // Pages may be empty but still continue, e.g. when none of
+                // their keys is a valid path
+                loop {
+                    if let Some(item) = state.buffer.pop() {
+                        return Some((Ok(item), state));
+                    }
+
+                    if state.done {
+                        return None;
+                    }
+
+                    let result = state
+                        .client
+                        .list_objects(
+                            state.prefix.as_deref(),
+                            state.continuation_token.as_deref(),
+                            Some(1000),
+                            None,
+                            state.start_after.as_deref(),
+                        )
+                        .await;
+
+                    match result {
+                        Ok(page) => {
+                            match page.next_continuation_token {
+                                Some(token) => state.continuation_token = Some(token),
+                                None => state.done = true,
+                            }
+
+                            state.buffer = page.objects;
+                            state.buffer.reverse();
+                        }
+                        Err(e) => {
+                            state.done = true;
+                            return Some((Err(e.into()), state));
+                        }
+                    }
+                }
+            },
+        )
+        .boxed()
+    }
+}
+
+/// Low-level multipart uploads, e.g. for several writers producing parts of one object
+///
+/// The [`MultipartId`](object_store::MultipartId) is the AIStore upload ID and
+/// [`PartId::content_id`](object_store::multipart::PartId) the etag of the part. Both
+/// are plain strings, so they can be handed between processes and turned back into
+/// the values [`MultipartStore::complete_multipart`](object_store::multipart::MultipartStore::complete_multipart)
+/// expects.
+#[async_trait::async_trait]
+impl object_store::multipart::MultipartStore for AiStore {
+    async fn create_multipart(
+        &self,
+        path: &object_store::path::Path,
+    ) -> object_store::Result<object_store::MultipartId> {
+        self.client
+            .initiate_multipart_upload(path, &Default::default())
+            .await
+            .map_err(Into::into)
+    }
+
+    async fn put_part(
+        &self,
+        path: &object_store::path::Path,
+        id: &object_store::MultipartId,
+        part_idx: usize,
+        data: object_store::PutPayload,
+    ) -> object_store::Result<object_store::multipart::PartId> {
+        let content_id = self
+            .client
+            .upload_part(path, id, part_number(part_idx)?, data)
+            .await
+            .map_err(object_store::Error::from)?;
+
+        Ok(object_store::multipart::PartId { content_id })
+    }
+
+    async fn complete_multipart(
+        &self,
+        path: &object_store::path::Path,
+        id: &object_store::MultipartId,
+        parts: Vec<object_store::multipart::PartId>,
+    ) -> object_store::Result<object_store::PutResult> {
+        let parts = parts
+            .into_iter()
+            .enumerate()
+            .map(|(idx, part)| Ok((part_number(idx)?, part.content_id)))
+            .collect::<object_store::Result<Vec<_>>>()?;
+
+        self.client
+            .complete_multipart_upload(path, id, parts)
+            .await
+            .map_err(Into::into)
+    }
+
+    async fn abort_multipart(
+        &self,
+        path: &object_store::path::Path,
+        id: &object_store::MultipartId,
+    ) -> object_store::Result<()> {
+        self.client
+            .abort_multipart_upload(path, id)
+            .await
+            .map_err(Into::into)
+    }
+}
+
+/// Sort `ranges` and merge the ones at most `coalesce` bytes apart
+fn merge_ranges(ranges: &[Range<u64>], coalesce: u64) -> Vec<Range<u64>> {
+    let mut ranges = ranges.to_vec();
+    ranges.sort_unstable_by_key(|range| range.start);
+
+    let mut merged: Vec<Range<u64>> = Vec::with_capacity(ranges.len());
+    for range in ranges {
+        match merged.last_mut() {
+            Some(last) if range.start <= last.end.saturating_add(coalesce) => {
+                last.end = last.end.max(range.end);
+            }
+            _ => merged.push(range),
+        }
+    }
+
+    merged
+}
+
+/// Split `0..size` into consecutive ranges of at most `chunk_size` bytes
+fn chunk_ranges(size: u64, chunk_size: u64) -> object_store::Result<Vec<Range<u64>>> {
+    if chunk_size == 0 {
+        return Err(AiStoreError::Configuration {
+            message: "Chunk size must be greater than 0".to_string(),
+        }
+        .into());
+    }
+
+    let step = usize::try_from(chunk_size).map_err(|_| AiStoreError::Configuration {
+        message: format!("Chunk size out of range: {chunk_size}"),
+    })?;
+
+    Ok((0..size)
+        .step_by(step)
+        .map(|start| start..(start + chunk_size).min(size))
+        .collect())
+}
+
+/// Fetch one chunk of a parallel read, failing if the object no longer has `e_tag`
+/// or the server returns more or fewer bytes than `range` asks for
+async fn fetch_chunk(
+    client: Arc<dyn Backend>,
+    location: object_store::path::Path,
+    range: Range<u64>,
+    e_tag: Option<String>,
+) -> object_store::Result<Bytes> {
+    let options = object_store::GetOptions {
+        range: Some(object_store::GetRange::Bounded(range.clone())),
+        if_match: e_tag.as_deref().map(backend::quote_etag),
+        ..Default::default()
+    };
+
+    let bytes = client.get_object(&location, options).await?.bytes().await?;
+    if bytes.len() as u64 != range.end - range.start {
+        return Err(AiStoreError::InvalidResponse {
+            message: format!(
+                "Chunk {:?} of {} has {} bytes",
+                range,
+                location,
+                bytes.len()
+            ),
+        }
+        .into());
+    }
+
+    Ok(bytes)
+}
+
+fn io_error(e: std::io::Error) -> object_store::Error {
+    object_store::Error::Generic {
+        store: "aistore",
+        source: Box::new(e),
+    }
+}
+
+/// Convert a zero-based part index into a one-based S3 part number
+fn part_number(part_idx: usize) -> object_store::Result<u32> {
+    u32::try_from(part_idx + 1).map_err(|_| {
+        AiStoreError::Configuration {
+            message: format!("Part index out of range: {part_idx}"),
+        }
+        .into()
+    })
 }
 
 struct ListState {
-    client: Arc<client::S3Client>,
+    client: Arc<dyn Backend>,
     prefix: Option<String>,
+    start_after: Option<String>,
     continuation_token: Option<String>,
     done: bool,
     buffer: Vec<object_store::ObjectMeta>,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{MockServer, Reply};
+    use object_store::{path::Path, ObjectStore};
+
+    /// A store for the bucket `bucket` on `server`, with further `options`
+    fn store(
+        server: &MockServer,
+        api: AiStoreApi,
+        options: &[(AiStoreConfigKey, &str)],
+    ) -> AiStore {
+        let builder = AiStoreBuilder::new()
+            .with_endpoint(server.url())
+            .with_bucket_name("bucket")
+            .with_api(api)
+            .with_allow_http(true);
+
+        options
+            .iter()
+            .fold(builder, |builder, (key, value)| {
+                builder.with_config(*key, *value)
+            })
+            .build()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn delete_stream_deletes_paths_before_an_error() {
+        let mut server = MockServer::bind().await;
+        server.serve(|_| Reply::status(200).body("<DeleteResult></DeleteResult>"));
+
+        let locations = futures::stream::iter([
+            Ok(Path::from("a")),
+            Ok(Path::from("b")),
+            Err(object_store::Error::NotImplemented),
+            Ok(Path::from("c")),
+        ])
+        .boxed();
+        let results: Vec<_> = store(&server, AiStoreApi::S3, &[])
+            .delete_stream(locations)
+            .collect()
+            .await;
+
+        assert_eq!(results.len(), 4);
+        assert_eq!(results[0].as_ref().unwrap().as_ref(), "a");
+        assert_eq!(results[1].as_ref().unwrap().as_ref(), "b");
+        assert!(matches!(
+            results[2],
+            Err(object_store::Error::NotImplemented)
+        ));
+        assert_eq!(results[3].as_ref().unwrap().as_ref(), "c");
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 2);
+        let batch = String::from_utf8(requests[0].body.clone()).unwrap();
+        assert!(batch.contains("<Key>a</Key>") && batch.contains("<Key>b</Key>"));
+    }
+
+    #[tokio::test]
+    async fn copy_to_uses_the_native_copy_action_for_qualified_buckets() {
+        let polls = std::sync::atomic::AtomicUsize::new(0);
+        let mut server = MockServer::bind().await;
+        server.serve(move |request| match request.method.as_str() {
+            "POST" => Reply::status(200).body("xid-1"),
+            _ => match polls.fetch_add(1, std::sync::atomic::Ordering::SeqCst) {
+                0 => Reply::status(200).body(r#"{"end_time": 0}"#),
+                _ => Reply::status(200).body(r#"{"end_time": 1700000000000000000}"#),
+            },
+        });
+
+        let store = store(
+            &server,
+            AiStoreApi::Native,
+            &[
+                (AiStoreConfigKey::Bucket, "src"),
+                (AiStoreConfigKey::Provider, "gcp"),
+            ],
+        );
+        let dest = Bucket {
+            name: "dst".to_string(),
+            provider: Provider::Ais,
+            namespace: Some(Namespace {
+                uuid: "remote".to_string(),
+                name: "ns".to_string(),
+            }),
+        };
+
+        store
+            .copy_to(dest.clone(), &Path::from("a/b"), &Path::from("copies/a/b"))
+            .await
+            .unwrap();
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 3);
+        assert_eq!(requests[0].target, "/v1/buckets/src?provider=gcp");
+        let body: serde_json::Value = serde_json::from_slice(&requests[0].body).unwrap();
+        assert_eq!(
+            body,
+            serde_json::json!({
+                "action": "copy-listrange",
+                "value": {
+                    "objnames": ["a/b"],
+                    "prepend": "copies/",
+                    "tobck": {
+                        "name": "dst",
+                        "provider": "ais",
+                        "namespace": {"uuid": "remote", "name": "ns"},
+                    },
+                },
+            })
+        );
+        assert_eq!(requests[1].target, "/v1/cluster?what=status");
+        assert_eq!(requests[1].body, br#"{"id":"xid-1"}"#);
+
+        // The action keeps object names, so other names cannot be copied to
+        let err = store
+            .copy_to(dest, &Path::from("a/b"), &Path::from("c"))
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(err, object_store::Error::NotSupported { .. }),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn ranges_are_sorted_and_merged() {
+        // Overlapping and unsorted
+        assert_eq!(merge_ranges(&[10..20, 0..5, 15..30], 0), vec![0..5, 10..30]);
+        // Adjacent
+        assert_eq!(merge_ranges(&[0..5, 5..10], 0), vec![0..10]);
+        // Gaps up to `coalesce` are bridged, larger ones are not
+        assert_eq!(merge_ranges(&[0..5, 8..10], 3), vec![0..10]);
+        assert_eq!(merge_ranges(&[0..5, 9..10], 3), vec![0..5, 9..10]);
+        // Contained
+        assert_eq!(merge_ranges(&[0..100, 10..20], 0), vec![0..100]);
+        assert_eq!(merge_ranges(&[], 10), Vec::<Range<u64>>::new());
+    }
+
+    /// Contents of the object `object_server` serves
+    const OBJECT: &str = "0123456789abcdefghij";
+
+    /// A server for `object` that answers HEADs and honors `Range` headers
+    async fn object_server(object: &'static str) -> MockServer {
+        let mut server = MockServer::bind().await;
+        server.serve(move |request| {
+            if request.method == "HEAD" {
+                return Reply::status(200)
+                    .header("etag", "\"e1\"")
+                    .cut_off_at(object.len());
+            }
+
+            let range = request
+                .header("range")
+                .unwrap()
+                .strip_prefix("bytes=")
+                .unwrap();
+            let (start, end) = range.split_once('-').unwrap();
+            let start: usize = start.parse().unwrap();
+            let end = (end.parse::<usize>().unwrap() + 1).min(object.len());
+            if start >= object.len() {
+                return Reply::status(416);
+            }
+            Reply::status(206)
+                .header("etag", "\"e1\"")
+                .header(
+                    "content-range",
+                    format!("bytes {}-{}/{}", start, end - 1, object.len()),
+                )
+                .body(&object[start..end])
+        });
+        server
+    }
+
+    #[tokio::test]
+    async fn get_ranges_slices_merged_fetches() {
+        let server = object_server(OBJECT).await;
+        let store = store(
+            &server,
+            AiStoreApi::S3,
+            &[(AiStoreConfigKey::RangeCoalesce, "4")],
+        );
+        let key = Path::from("key");
+
+        let ranges = store
+            .get_ranges(&key, &[12..14, 0..3, 2..5, 5..6, 15..25])
+            .await
+            .unwrap();
+        let ranges: Vec<_> = ranges.iter().map(|b| b.as_ref()).collect();
+        assert_eq!(ranges, [&b"cd"[..], b"012", b"234", b"5", b"fghij"]);
+        // 0..6 and 12..25 are fetched separately
+        assert_eq!(server.requests().len(), 2);
+
+        // The merged fetch 15..25 ends with the object, before 21..23 starts
+        let err = store.get_ranges(&key, &[15..18, 21..23]).await.unwrap_err();
+        assert!(matches!(err, object_store::Error::Generic { .. }), "{err}");
+    }
+
+    #[tokio::test]
+    async fn native_reads_of_old_versions_only_send_a_head() {
+        let mut server = MockServer::bind().await;
+        server.serve(|_| Reply::status(200).header("ais-version", "2"));
+        let store = store(&server, AiStoreApi::Native, &[]);
+
+        let options = object_store::GetOptions {
+            version: Some("1".to_string()),
+            ..Default::default()
+        };
+        let err = store
+            .get_opts(&Path::from("key"), options)
+            .await
+            .unwrap_err();
+
+        assert!(matches!(err, object_store::Error::NotFound { .. }), "{err}");
+        let requests = server.requests();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].method, "HEAD");
+    }
+
+    #[tokio::test]
+    async fn listing_continues_past_empty_pages() {
+        let mut server = MockServer::bind().await;
+        server.serve(|request| {
+            let body = if request.target.contains("continuation-token=t1") {
+                "<ListBucketResult><IsTruncated>false</IsTruncated>\
+                 <Contents><Key>dir/valid</Key><Size>1</Size></Contents></ListBucketResult>"
+            } else {
+                // The only key is not a valid path, so the page is empty
+                "<ListBucketResult><IsTruncated>true</IsTruncated>\
+                 <NextContinuationToken>t1</NextContinuationToken>\
+                 <Contents><Key>dir//invalid</Key><Size>1</Size></Contents></ListBucketResult>"
+            };
+            Reply::status(200).body(body)
+        });
+
+        let objects: Vec<_> = store(&server, AiStoreApi::S3, &[])
+            .list(None)
+            .try_collect()
+            .await
+            .unwrap();
+
+        assert_eq!(objects.len(), 1);
+        assert_eq!(objects[0].location.as_ref(), "dir/valid");
+        assert_eq!(server.requests().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn listings_with_an_offset_start_after_it() {
+        let mut server = MockServer::bind().await;
+        server.serve(|request| {
+            let body = if request.target.starts_with("/s3/") {
+                "<ListBucketResult><IsTruncated>false</IsTruncated>\
+                 <Contents><Key>dir/c</Key><Size>1</Size></Contents></ListBucketResult>"
+            } else {
+                r#"{"entries": [{"name": "dir/c", "size": 1}]}"#
+            };
+            Reply::status(200).body(body)
+        });
+        let offset = Path::from("dir/b");
+
+        for api in [AiStoreApi::S3, AiStoreApi::Native] {
+            let objects: Vec<_> = store(&server, api, &[])
+                .list_with_offset(None, &offset)
+                .try_collect()
+                .await
+                .unwrap();
+            assert_eq!(objects.len(), 1);
+            assert_eq!(objects[0].location.as_ref(), "dir/c");
+        }
+
+        let requests = server.requests();
+        assert!(
+            requests[0].target.contains("start-after=dir%2Fb"),
+            "{}",
+            requests[0].target
+        );
+        let message: serde_json::Value = serde_json::from_slice(&requests[1].body).unwrap();
+        assert_eq!(message["value"]["start_after"], "dir/b");
+    }
+
+    /// A server for one object that keeps the attribute headers of the last PUT
+    /// and returns them on reads
+    async fn attribute_server() -> MockServer {
+        let stored = std::sync::Mutex::new(vec![]);
+        let mut server = MockServer::bind().await;
+        server.serve(move |request| {
+            let mut stored = stored.lock().unwrap();
+            if request.method == "PUT" {
+                *stored = request
+                    .headers
+                    .iter()
+                    .filter(|(name, _)| {
+                        let name = name.to_ascii_lowercase();
+                        ["content-type", "cache-control", "ais-custom-md"].contains(&name.as_str())
+                            || name.starts_with("x-amz-meta-")
+                    })
+                    .cloned()
+                    .collect();
+            }
+            stored
+                .iter()
+                .fold(Reply::status(200).body("data"), |reply, (name, value)| {
+                    reply.header(name, value.clone())
+                })
+        });
+        server
+    }
+
+    #[tokio::test]
+    async fn attributes_survive_a_round_trip() {
+        let attributes = object_store::Attributes::from_iter([
+            (object_store::Attribute::ContentType, "text/plain"),
+            (object_store::Attribute::CacheControl, "max-age=60"),
+            (object_store::Attribute::Metadata("owner".into()), "alice"),
+            (object_store::Attribute::Metadata("team".into()), "ml"),
+        ]);
+
+        for api in [AiStoreApi::S3, AiStoreApi::Native] {
+            let server = attribute_server().await;
+            let store = store(&server, api, &[]);
+            let key = Path::from("key");
+
+            let opts = object_store::PutOptions::from(attributes.clone());
+            store.put_opts(&key, "data".into(), opts).await.unwrap();
+            let result = store.get(&key).await.unwrap();
+            assert_eq!(result.attributes, attributes, "{api:?}");
+
+            let put = &server.requests()[0];
+            match api {
+                AiStoreApi::S3 => {
+                    assert_eq!(put.header("x-amz-meta-owner"), Some("alice"));
+                    assert_eq!(put.header("ais-custom-md"), None);
+                }
+                AiStoreApi::Native => {
+                    let custom_md = put.header("ais-custom-md").unwrap();
+                    let mut pairs: Vec<_> = custom_md.split(',').collect();
+                    pairs.sort_unstable();
+                    assert_eq!(pairs, ["owner=alice", "team=ml"]);
+                    assert_eq!(put.header("x-amz-meta-owner"), None);
+                }
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn native_metadata_must_not_contain_separators() {
+        let server = attribute_server().await;
+        let store = store(&server, AiStoreApi::Native, &[]);
+
+        for (key, value) in [("a,b", "v"), ("a=b", "v"), ("k", "x,y"), ("k", "x=y")] {
+            let attributes = object_store::Attributes::from_iter([(
+                object_store::Attribute::Metadata(key.into()),
+                value,
+            )]);
+            let opts = object_store::PutOptions::from(attributes);
+            let err = store
+                .put_opts(&Path::from("key"), "data".into(), opts)
+                .await
+                .unwrap_err();
+            assert!(
+                err.to_string().contains("',' or '='"),
+                "{key}={value}: {err}"
+            );
+        }
+        assert!(server.requests().is_empty());
+    }
+
+    /// Objects and sorted common prefixes of a delimiter listing of `dir`
+    async fn list_dir(store: &AiStore) -> (Vec<String>, Vec<String>) {
+        let result = store
+            .list_with_delimiter(Some(&Path::from("dir")))
+            .await
+            .unwrap();
+        let objects = result.objects.iter().map(|o| o.location.to_string());
+        let mut prefixes: Vec<_> = result
+            .common_prefixes
+            .iter()
+            .map(|p| p.to_string())
+            .collect();
+        prefixes.sort_unstable();
+        (objects.collect(), prefixes)
+    }
+
+    #[tokio::test]
+    async fn delimiter_listings_use_server_side_common_prefixes() {
+        let mut server = MockServer::bind().await;
+        server.serve(|_| {
+            Reply::status(200).body(
+                "<ListBucketResult><IsTruncated>false</IsTruncated>\
+                 <Contents><Key>dir/a</Key><Size>1</Size></Contents>\
+                 <CommonPrefixes><Prefix>dir/sub/</Prefix></CommonPrefixes></ListBucketResult>",
+            )
+        });
+
+        let (objects, prefixes) = list_dir(&store(&server, AiStoreApi::S3, &[])).await;
+
+        assert_eq!(objects, ["dir/a"]);
+        assert_eq!(prefixes, ["dir/sub"]);
+        let requests = server.requests();
+        assert_eq!(requests.len(), 1);
+        assert!(
+            requests[0].target.contains("prefix=dir%2F"),
+            "{}",
+            requests[0].target
+        );
+        assert!(
+            requests[0].target.contains("delimiter=%2F"),
+            "{}",
+            requests[0].target
+        );
+    }
+
+    #[tokio::test]
+    async fn delimiter_listings_are_grouped_when_the_server_does_not() {
+        let mut server = MockServer::bind().await;
+        server.serve(|_| {
+            Reply::status(200).body(
+                "<ListBucketResult><IsTruncated>false</IsTruncated>\
+                 <Contents><Key>dir/a</Key><Size>1</Size></Contents>\
+                 <Contents><Key>dir/sub/b</Key><Size>1</Size></Contents>\
+                 <Contents><Key>dir/sub/c</Key><Size>1</Size></Contents>\
+                 <Contents><Key>dir/other/deep/d</Key><Size>1</Size></Contents>\
+                 </ListBucketResult>",
+            )
+        });
+
+        let (objects, prefixes) = list_dir(&store(&server, AiStoreApi::S3, &[])).await;
+
+        assert_eq!(objects, ["dir/a"]);
+        assert_eq!(prefixes, ["dir/other", "dir/sub"]);
+    }
+
+    #[tokio::test]
+    async fn native_delimiter_listings_are_grouped() {
+        let mut server = MockServer::bind().await;
+        server.serve(|_| {
+            Reply::status(200).body(
+                r#"{"entries": [
+                    {"name": "dir/a", "size": 1},
+                    {"name": "dir/sub/b", "size": 1},
+                    {"name": "dir/sub/c", "size": 1}
+                ]}"#,
+            )
+        });
+        let store = store(&server, AiStoreApi::Native, &[]);
+
+        let (objects, prefixes) = list_dir(&store).await;
+
+        assert_eq!(objects, ["dir/a"]);
+        assert_eq!(prefixes, ["dir/sub"]);
+    }
+
+    #[tokio::test]
+    async fn conditional_copies_are_checked_by_the_server() {
+        let mut server = MockServer::bind().await;
+        server.serve(|request| match request.target.as_str() {
+            "/s3/bucket/exists" => Reply::status(412),
+            _ => Reply::status(200),
+        });
+        let store = store(&server, AiStoreApi::S3, &[]);
+        let from = Path::from("from");
+
+        store
+            .copy_if_not_exists(&from, &Path::from("new"))
+            .await
+            .unwrap();
+        let err = store
+            .copy_if_not_exists(&from, &Path::from("exists"))
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(err, object_store::Error::AlreadyExists { .. }),
+            "{err}"
+        );
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 2);
+        for request in &requests {
+            assert_eq!(request.method, "PUT");
+            assert_eq!(request.header("x-amz-copy-source"), Some("bucket/from"));
+            assert_eq!(request.header("if-none-match"), Some("*"));
+        }
+    }
+
+    #[tokio::test]
+    async fn head_then_copy_checks_the_destination_first() {
+        let mut server = MockServer::bind().await;
+        server.serve(
+            |request| match (request.method.as_str(), request.target.as_str()) {
+                ("HEAD", "/s3/bucket/exists") => Reply::status(200),
+                ("HEAD", _) => Reply::status(404),
+                _ => Reply::status(200),
+            },
+        );
+        let store = store(
+            &server,
+            AiStoreApi::S3,
+            &[(AiStoreConfigKey::CopyIfNotExists, "head-then-copy")],
+        );
+        let from = Path::from("from");
+
+        let err = store
+            .copy_if_not_exists(&from, &Path::from("exists"))
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(err, object_store::Error::AlreadyExists { .. }),
+            "{err}"
+        );
+        assert_eq!(server.requests().len(), 1);
+
+        store
+            .copy_if_not_exists(&from, &Path::from("new"))
+            .await
+            .unwrap();
+        let requests = server.requests();
+        assert_eq!(requests.len(), 3);
+        assert_eq!(requests[1].method, "HEAD");
+        assert_eq!(requests[2].method, "PUT");
+        assert_eq!(requests[2].target, "/s3/bucket/new");
+        assert_eq!(requests[2].header("x-amz-copy-source"), Some("bucket/from"));
+        assert_eq!(requests[2].header("if-none-match"), None);
+    }
+
+    /// A server where `exists` exists and every other request succeeds
+    async fn rename_server() -> MockServer {
+        let mut server = MockServer::bind().await;
+        server.serve(|request| match request.method.as_str() {
+            "HEAD" if request.target.contains("/exists") => Reply::status(200),
+            "HEAD" => Reply::status(404),
+            "DELETE" => Reply::status(204),
+            _ => Reply::status(200),
+        });
+        server
+    }
+
+    #[tokio::test]
+    async fn renames_use_the_native_action() {
+        for mode in ["conditional", "head-then-copy"] {
+            let server = rename_server().await;
+            let store = store(
+                &server,
+                AiStoreApi::Native,
+                &[(AiStoreConfigKey::CopyIfNotExists, mode)],
+            );
+            let from = Path::from("from");
+
+            store.rename(&from, &Path::from("to")).await.unwrap();
+            store
+                .rename_if_not_exists(&from, &Path::from("new"))
+                .await
+                .unwrap();
+            let err = store
+                .rename_if_not_exists(&from, &Path::from("exists"))
+                .await
+                .unwrap_err();
+            assert!(
+                matches!(err, object_store::Error::AlreadyExists { .. }),
+                "{err}"
+            );
+
+            let requests: Vec<_> = server
+                .requests()
+                .into_iter()
+                .map(|request| {
+                    let target = request.target.split('?').next().unwrap().to_string();
+                    let body: Option<serde_json::Value> =
+                        serde_json::from_slice(&request.body).ok();
+                    (request.method, target, body)
+                })
+                .collect();
+            let rename = |to: &str| {
+                (
+                    "POST".to_string(),
+                    "/v1/objects/bucket/from".to_string(),
+                    Some(serde_json::json!({"action": "rename-obj", "name": to})),
+                )
+            };
+            let head = |path: &str| {
+                (
+                    "HEAD".to_string(),
+                    format!("/v1/objects/bucket/{path}"),
+                    None,
+                )
+            };
+            assert_eq!(
+                requests,
+                [rename("to"), head("new"), rename("new"), head("exists")],
+                "{mode}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn renames_fall_back_to_copy_and_delete() {
+        for (mode, if_none_match) in [("conditional", Some("*")), ("head-then-copy", None)] {
+            let server = rename_server().await;
+            let store = store(
+                &server,
+                AiStoreApi::S3,
+                &[(AiStoreConfigKey::CopyIfNotExists, mode)],
+            );
+            let from = Path::from("from");
+
+            store.rename(&from, &Path::from("to")).await.unwrap();
+            store
+                .rename_if_not_exists(&from, &Path::from("new"))
+                .await
+                .unwrap();
+            let err = store
+                .rename_if_not_exists(&from, &Path::from("exists"))
+                .await
+                .unwrap_err();
+            assert!(
+                matches!(err, object_store::Error::AlreadyExists { .. }),
+                "{err}"
+            );
+
+            let requests = server.requests();
+            let targets: Vec<_> = requests
+                .iter()
+                .map(|request| (request.method.as_str(), request.target.as_str()))
+                .collect();
+            assert_eq!(
+                targets,
+                [
+                    ("PUT", "/s3/bucket/to"),
+                    ("DELETE", "/s3/bucket/from"),
+                    ("HEAD", "/s3/bucket/new"),
+                    ("PUT", "/s3/bucket/new"),
+                    ("DELETE", "/s3/bucket/from"),
+                    ("HEAD", "/s3/bucket/exists"),
+                ],
+                "{mode}"
+            );
+            assert_eq!(requests[0].header("if-none-match"), None);
+            assert_eq!(requests[3].header("if-none-match"), if_none_match);
+            assert_eq!(requests[3].header("x-amz-copy-source"), Some("bucket/from"));
+        }
+    }
+
+    #[test]
+    fn objects_are_split_into_chunks() {
+        assert_eq!(chunk_ranges(20, 8).unwrap(), vec![0..8, 8..16, 16..20]);
+        assert_eq!(chunk_ranges(16, 8).unwrap(), vec![0..8, 8..16]);
+        assert_eq!(chunk_ranges(5, 8).unwrap(), vec![0..5]);
+        assert_eq!(chunk_ranges(0, 8).unwrap(), Vec::<Range<u64>>::new());
+        assert!(chunk_ranges(20, 0).is_err());
+    }
+
+    #[tokio::test]
+    async fn parallel_reads_yield_chunks_in_order() {
+        let server = object_server(OBJECT).await;
+        let store = store(&server, AiStoreApi::S3, &[]);
+        let key = Path::from("key");
+
+        let chunks: Vec<Bytes> = store
+            .get_parallel(&key, 6, 3)
+            .await
+            .unwrap()
+            .try_collect()
+            .await
+            .unwrap();
+        let chunks: Vec<_> = chunks.iter().map(|b| b.as_ref()).collect();
+        assert_eq!(chunks, [&b"012345"[..], b"6789ab", b"cdefgh", b"ij"]);
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 5);
+        assert_eq!(requests[0].method, "HEAD");
+        for request in &requests[1..] {
+            assert_eq!(request.header("if-match"), Some("\"e1\""));
+        }
+
+        let err = store.get_parallel(&key, 0, 3).await.err().unwrap();
+        assert!(matches!(err, object_store::Error::Generic { .. }), "{err}");
+    }
+
+    #[tokio::test]
+    async fn downloads_write_chunks_at_their_offsets() {
+        let server = object_server(OBJECT).await;
+        let store = store(&server, AiStoreApi::S3, &[]);
+        let key = Path::from("key");
+        let path = std::env::temp_dir().join(format!("aistore-download-{}", std::process::id()));
+
+        let size = store.download_to_file(&key, &path, 3, 4).await.unwrap();
+        assert_eq!(size, OBJECT.len() as u64);
+        assert_eq!(std::fs::read(&path).unwrap(), OBJECT.as_bytes());
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 1 + 7);
+        for request in &requests[1..] {
+            assert_eq!(request.header("if-match"), Some("\"e1\""));
+        }
+
+        let err = store.download_to_file(&key, &path, 0, 4).await.unwrap_err();
+        assert!(matches!(err, object_store::Error::Generic { .. }), "{err}");
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn chunks_of_the_wrong_length_fail() {
+        let mut server = MockServer::bind().await;
+        server.serve(|request| match request.method.as_str() {
+            "HEAD" => Reply::status(200).header("etag", "\"e1\"").cut_off_at(12),
+            // Claims the requested range but sends one byte more
+            _ => Reply::status(206)
+                .header("etag", "\"e1\"")
+                .header("content-range", "bytes 0-5/12")
+                .body("0123456"),
+        });
+        let store = store(&server, AiStoreApi::S3, &[]);
+        let key = Path::from("key");
+        let path = std::env::temp_dir().join(format!("aistore-short-{}", std::process::id()));
+
+        let result: object_store::Result<Vec<Bytes>> = store
+            .get_parallel(&key, 6, 1)
+            .await
+            .unwrap()
+            .try_collect()
+            .await;
+        let err = result.unwrap_err();
+        assert!(err.to_string().contains("has 7 bytes"), "{err}");
+
+        let err = store.download_to_file(&key, &path, 6, 1).await.unwrap_err();
+        assert!(matches!(err, object_store::Error::Generic { .. }), "{err}");
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn parallel_reads_of_empty_objects() {
+        let server = object_server("").await;
+        let store = store(&server, AiStoreApi::S3, &[]);
+        let key = Path::from("key");
+        let path = std::env::temp_dir().join(format!("aistore-empty-{}", std::process::id()));
+
+        let chunks: Vec<Bytes> = store
+            .get_parallel(&key, 6, 3)
+            .await
+            .unwrap()
+            .try_collect()
+            .await
+            .unwrap();
+        assert!(chunks.is_empty());
+
+        std::fs::write(&path, b"stale").unwrap();
+        assert_eq!(store.download_to_file(&key, &path, 6, 3).await.unwrap(), 0);
+        assert!(std::fs::read(&path).unwrap().is_empty());
+
+        // Only the HEADs, there is nothing to fetch
+        assert_eq!(server.requests().len(), 2);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn native_uploads_carry_the_checksum() {
+        let mut server = MockServer::bind().await;
+        server.serve(|_| Reply::status(200));
+        let store = store(
+            &server,
+            AiStoreApi::Native,
+            &[(AiStoreConfigKey::Checksum, "xxhash")],
+        );
+        let key = Path::from("key");
+
+        store.put(&key, "abc".into()).await.unwrap();
+        let requests = server.requests();
+        assert_eq!(requests[0].header("ais-checksum-type"), Some("xxhash"));
+        assert_eq!(
+            requests[0].header("ais-checksum-value"),
+            Some("44bc2cf5ad770999")
+        );
+
+        // Parts go through the S3 API, which has no xxhash header
+        let err = store.put_multipart(&key).await.err().unwrap();
+        assert!(
+            matches!(err, object_store::Error::NotSupported { .. }),
+            "{err}"
+        );
+        assert_eq!(server.requests().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn multipart_store_round_trip() {
+        use object_store::multipart::MultipartStore;
+
+        let mut server = MockServer::bind().await;
+        server.serve(|request| match request.method.as_str() {
+            "POST" if request.target.ends_with("?uploads") => {
+                Reply::status(200).header("x-ais-upload-id", "up/load id+1")
+            }
+            "PUT" => {
+                let part = if request.target.contains("partNumber=1&") {
+                    "\"etag-1\""
+                } else {
+                    "\"etag-2\""
+                };
+                Reply::status(200).header("etag", part)
+            }
+            "POST" => Reply::status(200).header("etag", "\"final\""),
+            _ => Reply::status(204),
+        });
+        let store = store(&server, AiStoreApi::S3, &[]);
+        let key = Path::from("key");
+
+        let id = store.create_multipart(&key).await.unwrap();
+        assert_eq!(id, "up/load id+1");
+        let first = store.put_part(&key, &id, 0, "a".into()).await.unwrap();
+        let second = store.put_part(&key, &id, 1, "b".into()).await.unwrap();
+        assert_eq!(first.content_id, "etag-1");
+        assert_eq!(second.content_id, "etag-2");
+
+        let result = store
+            .complete_multipart(&key, &id, vec![first, second])
+            .await
+            .unwrap();
+        assert_eq!(result.e_tag.as_deref(), Some("final"));
+        store.abort_multipart(&key, &id).await.unwrap();
+
+        let requests = server.requests();
+        let targets: Vec<_> = requests
+            .iter()
+            .map(|request| (request.method.as_str(), request.target.as_str()))
+            .collect();
+        assert_eq!(
+            targets,
+            [
+                ("POST", "/s3/bucket/key?uploads"),
+                (
+                    "PUT",
+                    "/s3/bucket/key?partNumber=1&uploadId=up%2Fload%20id%2B1"
+                ),
+                (
+                    "PUT",
+                    "/s3/bucket/key?partNumber=2&uploadId=up%2Fload%20id%2B1"
+                ),
+                ("POST", "/s3/bucket/key?uploadId=up%2Fload%20id%2B1"),
+                ("DELETE", "/s3/bucket/key?uploadId=up%2Fload%20id%2B1"),
+            ]
+        );
+        let complete = String::from_utf8(requests[3].body.clone()).unwrap();
+        assert!(
+            complete.contains("<PartNumber>1</PartNumber><ETag>etag-1</ETag>")
+                && complete.contains("<PartNumber>2</PartNumber><ETag>etag-2</ETag>"),
+            "{complete}"
+        );
+    }
+}
@@ -0,0 +1,190 @@
+//! A minimal HTTP/1.1 server that records requests, for tests
+
+use std::sync::{Arc, Mutex};
+
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+
+/// A request as the server received it
+#[derive(Debug, Clone)]
+pub(crate) struct Recorded {
+    pub method: String,
+    /// Request target as sent, i.e. the encoded path and query
+    pub target: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Recorded {
+    pub(crate) fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// Response the handler gives to a request
+#[derive(Debug, Clone)]
+pub(crate) struct Reply {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+    /// Announced body length, if the body is to be cut short
+    pub content_length: Option<usize>,
+    /// Send the body as one chunk of a chunked body that never ends
+    pub unterminated: bool,
+}
+
+impl Reply {
+    pub(crate) fn status(status: u16) -> Self {
+        Self {
+            status,
+            headers: vec![],
+            body: String::new(),
+            content_length: None,
+            unterminated: false,
+        }
+    }
+
+    pub(crate) fn header(mut self, name: &str, value: impl Into<String>) -> Self {
+        self.headers.push((name.to_string(), value.into()));
+        self
+    }
+
+    pub(crate) fn body(mut self, body: impl Into<String>) -> Self {
+        self.body = body.into();
+        self
+    }
+
+    /// Announce `length` bytes but send only the body, as a dropped connection would
+    pub(crate) fn cut_off_at(mut self, length: usize) -> Self {
+        self.content_length = Some(length);
+        self
+    }
+
+    /// Send the body without a length and drop the connection after it
+    pub(crate) fn cut_off_unannounced(mut self) -> Self {
+        self.unterminated = true;
+        self
+    }
+}
+
+pub(crate) struct MockServer {
+    listener: Option<TcpListener>,
+    url: String,
+    requests: Arc<Mutex<Vec<Recorded>>>,
+}
+
+impl MockServer {
+    /// Listen on a free local port; requests are served once `serve` is called
+    pub(crate) async fn bind() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+
+        Self {
+            listener: Some(listener),
+            url,
+            requests: Arc::default(),
+        }
+    }
+
+    /// Answer every request with `handler`, one connection per request
+    pub(crate) fn serve(&mut self, handler: impl Fn(&Recorded) -> Reply + Send + Sync + 'static) {
+        let listener = self.listener.take().expect("already serving");
+        let requests = Arc::clone(&self.requests);
+        let handler = Arc::new(handler);
+
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let requests = Arc::clone(&requests);
+                let handler = Arc::clone(&handler);
+                tokio::spawn(async move { serve_request(stream, &*handler, &requests).await });
+            }
+        });
+    }
+
+    pub(crate) fn url(&self) -> &str {
+        &self.url
+    }
+
+    pub(crate) fn requests(&self) -> Vec<Recorded> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+/// Read one request, record it and send the handler's reply
+async fn serve_request(
+    stream: TcpStream,
+    handler: &(dyn Fn(&Recorded) -> Reply + Send + Sync),
+    requests: &Mutex<Vec<Recorded>>,
+) -> Option<()> {
+    let mut stream = BufReader::new(stream);
+
+    let mut line = String::new();
+    stream.read_line(&mut line).await.ok()?;
+    let mut request_line = line.split_whitespace();
+    let method = request_line.next()?.to_string();
+    let target = request_line.next()?.to_string();
+
+    let mut headers = vec![];
+    loop {
+        line.clear();
+        stream.read_line(&mut line).await.ok()?;
+        let header = line.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        let (name, value) = header.split_once(':')?;
+        headers.push((name.trim().to_string(), value.trim().to_string()));
+    }
+
+    let mut request = Recorded {
+        method,
+        target,
+        headers,
+        body: vec![],
+    };
+
+    if request.header("transfer-encoding") == Some("chunked") {
+        loop {
+            line.clear();
+            stream.read_line(&mut line).await.ok()?;
+            let size = usize::from_str_radix(line.trim_end(), 16).ok()?;
+            let mut chunk = vec![0; size + 2];
+            stream.read_exact(&mut chunk).await.ok()?;
+            if size == 0 {
+                break;
+            }
+            request.body.extend_from_slice(&chunk[..size]);
+        }
+    } else if let Some(length) = request.header("content-length") {
+        let mut body = vec![0; length.parse().ok()?];
+        stream.read_exact(&mut body).await.ok()?;
+        request.body = body;
+    }
+
+    let reply = handler(&request);
+    requests.lock().unwrap().push(request);
+
+    let mut response = format!("HTTP/1.1 {} Mock\r\nconnection: close\r\n", reply.status);
+    if reply.unterminated {
+        response.push_str("transfer-encoding: chunked\r\n");
+    } else {
+        let length = reply.content_length.unwrap_or(reply.body.len());
+        response.push_str(&format!("content-length: {length}\r\n"));
+    }
+    for (name, value) in &reply.headers {
+        response.push_str(&format!("{name}: {value}\r\n"));
+    }
+    response.push_str("\r\n");
+    if reply.unterminated {
+        response.push_str(&format!("{:x}\r\n{}\r\n", reply.body.len(), reply.body));
+    } else {
+        response.push_str(&reply.body);
+    }
+
+    let mut stream = stream.into_inner();
+    stream.write_all(response.as_bytes()).await.ok()?;
+    stream.shutdown().await.ok()
+}
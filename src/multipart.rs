@@ -1,33 +1,35 @@
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 
-use bytes::Bytes;
 use object_store::{path::Path, MultipartUpload, PutPayload, PutResult};
-use tokio::sync::Mutex;
+use tokio::sync::Semaphore;
 
-use crate::client::S3Client;
+use crate::backend::Backend;
 
 pub struct AiStoreMultipartUpload {
-    client: Arc<S3Client>,
+    client: Arc<dyn Backend>,
     location: Path,
     upload_id: String,
-    state: Arc<Mutex<MultipartState>>,
-}
-
-struct MultipartState {
-    parts: Vec<(u32, String)>,
+    /// Part number handed to the next `put_part` call
     next_part_number: u32,
+    /// Limits the number of parts uploading at the same time
+    in_flight: Arc<Semaphore>,
+    parts: Arc<Mutex<Vec<(u32, String)>>>,
 }
 
 impl AiStoreMultipartUpload {
-    pub fn new(client: Arc<S3Client>, location: Path, upload_id: String) -> Self {
+    pub fn new(
+        client: Arc<dyn Backend>,
+        location: Path,
+        upload_id: String,
+        max_concurrent_parts: usize,
+    ) -> Self {
         Self {
             client,
             location,
             upload_id,
-            state: Arc::new(Mutex::new(MultipartState {
-                parts: Vec::new(),
-                next_part_number: 1,
-            })),
+            next_part_number: 1,
+            in_flight: Arc::new(Semaphore::new(max_concurrent_parts.max(1))),
+            parts: Arc::new(Mutex::new(Vec::new())),
         }
     }
 }
@@ -38,31 +40,26 @@ impl MultipartUpload for AiStoreMultipartUpload {
         let client = self.client.clone();
         let location = self.location.clone();
         let upload_id = self.upload_id.clone();
-        let state = self.state.clone();
+        let in_flight = self.in_flight.clone();
+        let parts = self.parts.clone();
+
+        // Assigned here rather than in the future so that the part order
+        // follows the order of `put_part` calls, not of completion
+        let part_number = self.next_part_number;
+        self.next_part_number += 1;
 
         Box::pin(async move {
-            let part_number = {
-                let mut state = state.lock().await;
-                let num = state.next_part_number;
-                state.next_part_number += 1;
-                num
-            };
-
-            let mut bytes = Vec::new();
-            for chunk in data {
-                bytes.extend_from_slice(&chunk);
-            }
-            let data = Bytes::from(bytes);
+            let _permit = in_flight
+                .acquire_owned()
+                .await
+                .expect("semaphore is never closed");
 
             let etag = client
                 .upload_part(&location, &upload_id, part_number, data)
                 .await
                 .map_err(object_store::Error::from)?;
 
-            {
-                let mut state = state.lock().await;
-                state.parts.push((part_number, etag));
-            }
+            parts.lock().unwrap().push((part_number, etag));
 
             Ok(())
         })
@@ -70,8 +67,7 @@ impl MultipartUpload for AiStoreMultipartUpload {
 
     async fn complete(&mut self) -> object_store::Result<PutResult> {
         let parts = {
-            let state = self.state.lock().await;
-            let mut parts = state.parts.clone();
+            let mut parts = self.parts.lock().unwrap().clone();
             parts.sort_by_key(|(num, _)| *num);
             parts
         };
@@ -98,3 +94,108 @@ impl std::fmt::Debug for AiStoreMultipartUpload {
             .finish()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    use object_store::ObjectStore;
+
+    use crate::mock::{MockServer, Recorded, Reply};
+    use crate::{AiStore, AiStoreBuilder};
+
+    /// Part number of a part upload, from its query
+    fn part_number(request: &Recorded) -> Option<u32> {
+        let (_, query) = request.target.split_once("partNumber=")?;
+        query.split('&').next()?.parse().ok()
+    }
+
+    /// A server that answers multipart requests, taking `delay(n)` for part `n`
+    ///
+    /// The delay blocks the server's thread, so tests need a multi-threaded runtime.
+    async fn multipart_server(
+        delay: impl Fn(u32) -> Duration + Send + Sync + 'static,
+        in_flight: std::sync::Arc<AtomicUsize>,
+        max_in_flight: std::sync::Arc<AtomicUsize>,
+    ) -> MockServer {
+        let mut server = MockServer::bind().await;
+        server.serve(
+            move |request| match (request.method.as_str(), part_number(request)) {
+                ("PUT", Some(n)) => {
+                    let now = in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+                    max_in_flight.fetch_max(now, Ordering::SeqCst);
+                    std::thread::sleep(delay(n));
+                    in_flight.fetch_sub(1, Ordering::SeqCst);
+                    Reply::status(200).header("etag", format!("\"etag-{n}\""))
+                }
+                _ => Reply::status(200).header("x-ais-upload-id", "upload"),
+            },
+        );
+        server
+    }
+
+    fn store(server: &MockServer, concurrency: usize) -> AiStore {
+        AiStoreBuilder::new()
+            .with_endpoint(server.url())
+            .with_bucket_name("bucket")
+            .with_allow_http(true)
+            .with_multipart_concurrency(concurrency)
+            .build()
+            .unwrap()
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn parts_are_completed_in_call_order() {
+        let server = multipart_server(
+            |n| Duration::from_millis(if n == 1 { 300 } else { 0 }),
+            Default::default(),
+            Default::default(),
+        )
+        .await;
+        let store = store(&server, 8);
+
+        let mut upload = store
+            .put_multipart(&object_store::path::Path::from("key"))
+            .await
+            .unwrap();
+        let first = upload.put_part("a".into());
+        let second = upload.put_part("b".into());
+        futures::future::try_join(first, second).await.unwrap();
+        upload.complete().await.unwrap();
+
+        let requests = server.requests();
+        let finished: Vec<_> = requests.iter().filter_map(part_number).collect();
+        assert_eq!(finished, [2, 1]);
+
+        let complete = String::from_utf8(requests.last().unwrap().body.clone()).unwrap();
+        let first = complete.find("<PartNumber>1</PartNumber><ETag>etag-1</ETag>");
+        let second = complete.find("<PartNumber>2</PartNumber><ETag>etag-2</ETag>");
+        assert!(first.unwrap() < second.unwrap(), "{complete}");
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 8)]
+    async fn parts_in_flight_are_limited() {
+        let in_flight = std::sync::Arc::new(AtomicUsize::new(0));
+        let max_in_flight = std::sync::Arc::new(AtomicUsize::new(0));
+        let server = multipart_server(
+            |_| Duration::from_millis(100),
+            in_flight,
+            max_in_flight.clone(),
+        )
+        .await;
+        let store = store(&server, 2);
+
+        let mut upload = store
+            .put_multipart(&object_store::path::Path::from("key"))
+            .await
+            .unwrap();
+        let parts: Vec<_> = (0..6).map(|_| upload.put_part("part".into())).collect();
+        futures::future::try_join_all(parts).await.unwrap();
+        upload.complete().await.unwrap();
+
+        assert_eq!(max_in_flight.load(Ordering::SeqCst), 2);
+        let parts = server.requests().iter().filter_map(part_number).count();
+        assert_eq!(parts, 6);
+    }
+}
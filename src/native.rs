@@ -0,0 +1,528 @@
+use std::sync::Arc;
+use std::time::Instant;
+
+use chrono::{DateTime, Utc};
+use futures::StreamExt;
+use object_store::{
+    path::Path, Attributes, GetOptions, GetResult, ObjectMeta, PutOptions, PutPayload, PutResult,
+};
+
+use crate::backend::{self, Backend, ListPage, MetadataHeaders, RequestFactory};
+use crate::bucket::{Bucket, Provider};
+use crate::checksum::{self, ChecksumAlgorithm};
+use crate::client::S3Client;
+use crate::error::{AiStoreError, ErrorDetails};
+use crate::json::{
+    ActionMessage, CopyObjectsMessage, ListObjectsMessage, ListObjectsResult, XactionQuery,
+    XactionStatus,
+};
+use crate::request::{ClientExt, HttpClient, HttpRequestBuilder, RequestBody};
+
+/// Object properties requested from the `list` action
+const LIST_PROPS: &str = "name,size,atime,version";
+
+/// Go time layout for RFC 3339, in which `list` reports access times
+const LIST_TIME_FORMAT: &str = "2006-01-02T15:04:05Z07:00";
+
+#[derive(Debug, Clone)]
+pub(crate) struct NativeConfig {
+    pub endpoint: String,
+    pub bucket: Bucket,
+    pub checksum: Option<ChecksumAlgorithm>,
+}
+
+/// Client for the native AIStore REST API (`/v1/objects`, `/v1/buckets`)
+///
+/// The native API has no multipart upload, single-object copy or delete by
+/// version, so those are served through the S3 compatibility layer of the
+/// same cluster, and only work for the buckets that layer can address (see
+/// [`AiStoreApi::S3`](crate::AiStoreApi::S3)).
+#[derive(Debug, Clone)]
+pub(crate) struct NativeClient {
+    config: NativeConfig,
+    client: HttpClient,
+    s3: S3Client,
+}
+
+impl NativeClient {
+    pub(crate) fn new(config: NativeConfig, client: HttpClient, s3: S3Client) -> Self {
+        Self { config, client, s3 }
+    }
+
+    fn object_url(&self, path: &Path) -> String {
+        format!(
+            "{}/v1/objects/{}/{}",
+            self.config.endpoint,
+            self.config.bucket.name,
+            backend::encode_key(path)
+        )
+    }
+
+    fn bucket_url(&self) -> String {
+        format!(
+            "{}/v1/buckets/{}",
+            self.config.endpoint, self.config.bucket.name
+        )
+    }
+
+    /// Attach the provider and namespace that qualify the bucket
+    fn with_bucket(&self, request: HttpRequestBuilder) -> HttpRequestBuilder {
+        request.query_params(self.config.bucket.query_params())
+    }
+
+    /// Poll the status of the xaction `id` until it finishes
+    ///
+    /// Gives up once the retry timeout of the request policy has passed.
+    async fn wait_for_xaction(&self, id: &str) -> Result<(), AiStoreError> {
+        let url = format!("{}/v1/cluster", self.config.endpoint);
+        let body = serde_json::to_string(&XactionQuery { id: id.to_string() }).map_err(|e| {
+            AiStoreError::InvalidResponse {
+                message: format!("Failed to serialize xaction query: {}", e),
+            }
+        })?;
+
+        let policy = self.client.policy();
+        let started = Instant::now();
+        let mut delay = policy.initial_retry_delay;
+
+        loop {
+            let response = self
+                .client
+                .get_with_retry(&url)
+                .query("what", "status")
+                .header(
+                    reqwest::header::CONTENT_TYPE.to_string(),
+                    "application/json",
+                )
+                .body(RequestBody::Text(body.clone()))
+                .send()
+                .await?;
+
+            let status = response.status();
+            if !status.is_success() {
+                return Err(backend::handle_error_response(id, response).await);
+            }
+
+            let text = response
+                .text()
+                .await
+                .map_err(|e| AiStoreError::Request { source: e })?;
+            let xaction: XactionStatus =
+                serde_json::from_str(&text).map_err(|e| AiStoreError::InvalidResponse {
+                    message: format!("Failed to parse xaction status: {}", e),
+                })?;
+
+            if !xaction.err.is_empty() || xaction.aborted {
+                return Err(AiStoreError::InvalidResponse {
+                    message: format!("Xaction {} failed: {}", id, xaction.err),
+                });
+            }
+            if xaction.end_time != 0 {
+                return Ok(());
+            }
+
+            if !policy.within_budget(started, delay) {
+                return Err(AiStoreError::InvalidResponse {
+                    message: format!("Xaction {} did not finish in time", id),
+                });
+            }
+            tokio::time::sleep(delay).await;
+            delay = policy.next_retry_delay(delay);
+        }
+    }
+
+    /// The S3 client for `operation`, unless the bucket needs a provider or
+    /// namespace the S3 API cannot express
+    fn s3(&self, operation: &str) -> Result<&S3Client, AiStoreError> {
+        let bucket = &self.config.bucket;
+        if bucket.provider != Provider::Ais || bucket.namespace.is_some() {
+            return Err(AiStoreError::NotSupported {
+                message: format!("{} in {} through the S3 API", operation, bucket),
+            });
+        }
+
+        Ok(&self.s3)
+    }
+}
+
+#[async_trait::async_trait]
+impl Backend for NativeClient {
+    async fn put_object(
+        &self,
+        path: &Path,
+        payload: PutPayload,
+        opts: PutOptions,
+    ) -> Result<PutResult, AiStoreError> {
+        let url = self.object_url(path);
+        let content_length = payload.content_length();
+
+        let request = backend::apply_put_mode(
+            self.with_bucket(self.client.put_with_retry(url)),
+            &opts.mode,
+        )?;
+        let request =
+            backend::apply_attributes(request, &opts.attributes, MetadataHeaders::Native)?;
+        let request = match self.config.checksum {
+            Some(algorithm) => {
+                let digest = checksum::payload_checksum(algorithm, &payload);
+                checksum::apply_checksum(request, algorithm, &digest, MetadataHeaders::Native)
+            }
+            None => request,
+        };
+
+        let response = request
+            .header(
+                reqwest::header::CONTENT_LENGTH.as_str(),
+                content_length.to_string(),
+            )
+            .body(RequestBody::Payload(payload))
+            .send()
+            .await?;
+
+        let status = response.status();
+        if !status.is_success() {
+            return Err(backend::put_error_response(path, &opts.mode, response).await);
+        }
+
+        Ok(backend::put_result(&response))
+    }
+
+    async fn get_object(
+        &self,
+        path: &Path,
+        options: GetOptions,
+    ) -> Result<GetResult, AiStoreError> {
+        // The native API only serves the current version, so a request for any
+        // other version finds nothing. A HEAD tells without fetching the data.
+        if let Some(version) = options.version.as_ref().filter(|_| !options.head) {
+            let meta = self.head_object(path).await?;
+            if meta.version.as_ref() != Some(version) {
+                return Err(version_not_found(path, version));
+            }
+        }
+
+        let url = self.object_url(path);
+
+        let request = if options.head {
+            self.client.head_with_retry(&url)
+        } else {
+            self.client.get_with_retry(&url)
+        };
+
+        let response = backend::apply_get_options(self.with_bucket(request), &options)
+            .send()
+            .await?;
+
+        let client = self.client.clone();
+        let bucket = self.config.bucket.query_params();
+        let request: RequestFactory =
+            Arc::new(move || client.get_with_retry(&url).query_params(bucket.clone()));
+
+        let result =
+            backend::get_result(path, &options, response, request, self.config.checksum).await?;
+
+        // The object may have changed since the HEAD
+        match &options.version {
+            Some(version) if result.meta.version.as_ref() != Some(version) => {
+                Err(version_not_found(path, version))
+            }
+            _ => Ok(result),
+        }
+    }
+
+    async fn head_object(&self, path: &Path) -> Result<ObjectMeta, AiStoreError> {
+        let url = self.object_url(path);
+
+        let response = self
+            .with_bucket(self.client.head_with_retry(url))
+            .send()
+            .await?;
+
+        let status = response.status();
+        if !status.is_success() {
+            return Err(backend::handle_error_response(path.as_ref(), response).await);
+        }
+
+        backend::extract_object_meta(path, &response)
+    }
+
+    async fn delete_object(&self, path: &Path) -> Result<(), AiStoreError> {
+        let url = self.object_url(path);
+
+        let response = self
+            .with_bucket(self.client.delete_with_retry(url))
+            .send()
+            .await?;
+
+        let status = response.status();
+        if !status.is_success() {
+            return Err(backend::handle_error_response(path.as_ref(), response).await);
+        }
+
+        Ok(())
+    }
+
+    async fn list_objects(
+        &self,
+        prefix: Option<&str>,
+        continuation_token: Option<&str>,
+        max_keys: Option<u32>,
+        // The native listing is always recursive, `AiStore` groups the
+        // entries into directories itself
+        _delimiter: Option<&str>,
+        start_after: Option<&str>,
+    ) -> Result<ListPage, AiStoreError> {
+        let url = self.bucket_url();
+
+        let message = ActionMessage::new(
+            "list",
+            ListObjectsMessage {
+                prefix: prefix.map(|p| p.to_string()),
+                props: LIST_PROPS.to_string(),
+                continuation_token: continuation_token.map(|t| t.to_string()),
+                pagesize: max_keys,
+                start_after: start_after.map(|s| s.to_string()),
+                time_format: LIST_TIME_FORMAT,
+            },
+        );
+        let body = serde_json::to_string(&message).map_err(|e| AiStoreError::InvalidResponse {
+            message: format!("Failed to serialize list request: {}", e),
+        })?;
+
+        let response = self
+            .with_bucket(self.client.get_with_retry(url))
+            .header(
+                reqwest::header::CONTENT_TYPE.to_string(),
+                "application/json",
+            )
+            .body(RequestBody::Text(body))
+            .send()
+            .await?;
+
+        let status = response.status();
+        if !status.is_success() {
+            return Err(
+                backend::handle_error_response(&self.config.bucket.to_string(), response).await,
+            );
+        }
+
+        let body = response
+            .text()
+            .await
+            .map_err(|e| AiStoreError::Request { source: e })?;
+
+        let result: ListObjectsResult =
+            serde_json::from_str(&body).map_err(|e| AiStoreError::InvalidResponse {
+                message: format!("Failed to parse list response: {}", e),
+            })?;
+
+        Ok(ListPage {
+            objects: result
+                .entries
+                .into_iter()
+                .filter_map(|entry| {
+                    let location = Path::parse(&entry.name).ok()?;
+                    let last_modified = entry
+                        .atime
+                        .as_deref()
+                        .and_then(|s| {
+                            DateTime::parse_from_rfc3339(s)
+                                .or_else(|_| DateTime::parse_from_rfc2822(s))
+                                .ok()
+                        })
+                        .map(|dt| dt.with_timezone(&Utc))
+                        .unwrap_or_else(Utc::now);
+                    Some(ObjectMeta {
+                        location,
+                        last_modified,
+                        size: entry.size,
+                        e_tag: None,
+                        version: entry.version.filter(|v| !v.is_empty()),
+                    })
+                })
+                .collect(),
+            common_prefixes: vec![],
+            next_continuation_token: Some(result.continuation_token).filter(|t| !t.is_empty()),
+        })
+    }
+
+    async fn delete_object_version(&self, path: &Path, version: &str) -> Result<(), AiStoreError> {
+        self.s3("Delete by version")?
+            .delete_object_version(path, version)
+            .await
+    }
+
+    /// The native multi-object delete runs as an asynchronous xaction that reports
+    /// no per-object results, so batches go through the S3 DeleteObjects API.
+    /// Buckets the S3 API cannot address are deleted from object by object.
+    async fn delete_objects(
+        &self,
+        paths: Vec<Path>,
+    ) -> Result<Vec<Result<Path, AiStoreError>>, AiStoreError> {
+        if let Ok(s3) = self.s3("Batch delete") {
+            return s3.delete_objects(paths).await;
+        }
+
+        Ok(futures::stream::iter(paths)
+            .map(|path| async move {
+                // Like DeleteObjects, removing a missing key is not an error
+                match self.delete_object(&path).await {
+                    Ok(()) | Err(AiStoreError::NotFound { .. }) => Ok(path),
+                    Err(e) => Err(e),
+                }
+            })
+            .buffered(crate::DELETE_CONCURRENCY)
+            .collect()
+            .await)
+    }
+
+    async fn copy_object(
+        &self,
+        from: &Path,
+        to: &Path,
+        if_not_exists: bool,
+    ) -> Result<(), AiStoreError> {
+        self.s3("Copy")?.copy_object(from, to, if_not_exists).await
+    }
+
+    /// Plain AIS buckets are copied between through the S3 API. Other buckets
+    /// use the native `copy-listrange` action, which names the copy after the
+    /// source, so `to` has to be `from` with an optional prefix.
+    async fn copy_object_to(
+        &self,
+        from: &Path,
+        dest: &Bucket,
+        to: &Path,
+    ) -> Result<(), AiStoreError> {
+        if let Ok(s3) = self.s3("Copy") {
+            if dest.provider == Provider::Ais && dest.namespace.is_none() {
+                return s3.copy_object_to(from, dest, to).await;
+            }
+        }
+
+        let prepend =
+            to.as_ref()
+                .strip_suffix(from.as_ref())
+                .ok_or_else(|| AiStoreError::NotSupported {
+                    message: format!("Copy of {} to {} under another name in {}", from, to, dest),
+                })?;
+
+        let message = ActionMessage::new(
+            "copy-listrange",
+            CopyObjectsMessage {
+                objnames: vec![from.to_string()],
+                prepend: prepend.to_string(),
+                tobck: dest.into(),
+            },
+        );
+        let body = serde_json::to_string(&message).map_err(|e| AiStoreError::InvalidResponse {
+            message: format!("Failed to serialize copy request: {}", e),
+        })?;
+
+        let response = self
+            .with_bucket(self.client.post_with_retry(self.bucket_url()))
+            .header(
+                reqwest::header::CONTENT_TYPE.to_string(),
+                "application/json",
+            )
+            .body(RequestBody::Text(body))
+            .send()
+            .await?;
+
+        let status = response.status();
+        if !status.is_success() {
+            return Err(backend::handle_error_response(from.as_ref(), response).await);
+        }
+
+        let xaction = response
+            .text()
+            .await
+            .map_err(|e| AiStoreError::Request { source: e })?;
+
+        self.wait_for_xaction(xaction.trim()).await
+    }
+
+    async fn rename_object(&self, from: &Path, to: &Path) -> Result<(), AiStoreError> {
+        // AIStore only renames objects in its own buckets, not in the cloud
+        // buckets it fronts
+        if self.config.bucket.provider != Provider::Ais {
+            return Err(AiStoreError::NotSupported {
+                message: format!("Rename in {} buckets", self.config.bucket.provider.as_str()),
+            });
+        }
+
+        let url = self.object_url(from);
+
+        let message = ActionMessage::named("rename-obj", to.as_ref());
+        let body = serde_json::to_string(&message).map_err(|e| AiStoreError::InvalidResponse {
+            message: format!("Failed to serialize rename request: {}", e),
+        })?;
+
+        let response = self
+            .with_bucket(self.client.post_with_retry(url))
+            .header(
+                reqwest::header::CONTENT_TYPE.to_string(),
+                "application/json",
+            )
+            .body(RequestBody::Text(body))
+            .send()
+            .await?;
+
+        let status = response.status();
+        if !status.is_success() {
+            return Err(backend::handle_error_response(from.as_ref(), response).await);
+        }
+
+        Ok(())
+    }
+
+    async fn initiate_multipart_upload(
+        &self,
+        path: &Path,
+        attributes: &Attributes,
+    ) -> Result<String, AiStoreError> {
+        self.s3("Multipart upload")?
+            .initiate_multipart_upload(path, attributes)
+            .await
+    }
+
+    async fn upload_part(
+        &self,
+        path: &Path,
+        upload_id: &str,
+        part_number: u32,
+        data: PutPayload,
+    ) -> Result<String, AiStoreError> {
+        self.s3("Multipart upload")?
+            .upload_part(path, upload_id, part_number, data)
+            .await
+    }
+
+    async fn complete_multipart_upload(
+        &self,
+        path: &Path,
+        upload_id: &str,
+        parts: Vec<(u32, String)>,
+    ) -> Result<PutResult, AiStoreError> {
+        self.s3("Multipart upload")?
+            .complete_multipart_upload(path, upload_id, parts)
+            .await
+    }
+
+    async fn abort_multipart_upload(
+        &self,
+        path: &Path,
+        upload_id: &str,
+    ) -> Result<(), AiStoreError> {
+        self.s3("Multipart upload")?
+            .abort_multipart_upload(path, upload_id)
+            .await
+    }
+}
+
+fn version_not_found(path: &Path, version: &str) -> AiStoreError {
+    AiStoreError::NotFound {
+        path: path.to_string(),
+        details: ErrorDetails::new(format!("No version {}", version)),
+    }
+}
@@ -0,0 +1,187 @@
+//! Parsing of `ais://` style URLs
+
+use object_store::path::Path;
+
+use crate::bucket::{Bucket, Namespace, Provider};
+use crate::{AiStore, AiStoreBuilder, BuilderError};
+
+/// Bucket and object prefix addressed by a URL
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct AisUrl {
+    pub bucket: Bucket,
+    pub prefix: String,
+}
+
+/// Parse a URL of the form `{provider}://[@uuid[#namespace]/]{bucket}[/{prefix}]`
+///
+/// The scheme selects the provider (`ais`, `s3`/`aws`, `gs`/`gcp`, `az`/`azure`).
+/// A `url::Url` drops the `@` of an empty user-info, so `ais://uuid#namespace/bucket`
+/// is accepted as well.
+pub(crate) fn parse_url(url: &str) -> Result<AisUrl, BuilderError> {
+    let invalid = || BuilderError::InvalidUrl {
+        url: url.to_string(),
+    };
+
+    let (scheme, rest) = url.split_once("://").ok_or_else(invalid)?;
+    let provider: Provider = scheme.parse()?;
+
+    let (namespace, rest) = match rest.strip_prefix('@') {
+        Some(rest) => {
+            let (namespace, rest) = rest.split_once('/').ok_or_else(invalid)?;
+            (Some(format!("@{namespace}")), rest)
+        }
+        None => match rest.split_once('/') {
+            Some((host, rest)) if host.contains('#') => (Some(format!("@{host}")), rest),
+            _ => (None, rest),
+        },
+    };
+    let namespace = namespace.map(|n| n.parse::<Namespace>()).transpose()?;
+
+    let (name, prefix) = rest.split_once('/').unwrap_or((rest, ""));
+    if name.is_empty() {
+        return Err(invalid());
+    }
+
+    Ok(AisUrl {
+        bucket: Bucket {
+            name: name.to_string(),
+            provider,
+            namespace,
+        },
+        prefix: prefix.trim_end_matches('/').to_string(),
+    })
+}
+
+/// Create an [`AiStore`] from a URL and options, like [`object_store::parse_url_opts`]
+///
+/// Returns the store together with the object prefix the URL points at. Options
+/// are [`AiStoreConfigKey`](crate::AiStoreConfigKey) names; unknown keys are
+/// ignored. The endpoint has to be passed as an option, e.g. `endpoint`.
+///
+/// Accepts a `&str` or a `&url::Url`; prefer the string form for namespaced
+/// buckets with an empty namespace name (`ais://@uuid/bucket`), which a
+/// `url::Url` cannot represent.
+pub fn parse_url_opts<I, K, V>(
+    url: impl AsRef<str>,
+    options: I,
+) -> object_store::Result<(AiStore, Path)>
+where
+    I: IntoIterator<Item = (K, V)>,
+    K: AsRef<str>,
+    V: Into<String>,
+{
+    let url = url.as_ref();
+    let parsed = parse_url(url)?;
+
+    let builder = options.into_iter().fold(
+        AiStoreBuilder::new().with_url(url),
+        |builder, (key, value)| match key.as_ref().parse() {
+            Ok(key) => builder.with_config(key, value),
+            Err(_) => builder,
+        },
+    );
+
+    Ok((builder.build()?, Path::parse(parsed.prefix)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bucket(name: &str, provider: Provider, namespace: Option<(&str, &str)>) -> Bucket {
+        Bucket {
+            name: name.to_string(),
+            provider,
+            namespace: namespace.map(|(uuid, name)| Namespace {
+                uuid: uuid.to_string(),
+                name: name.to_string(),
+            }),
+        }
+    }
+
+    fn parsed(url: &str) -> (Bucket, String) {
+        let parsed = parse_url(url).unwrap();
+        (parsed.bucket, parsed.prefix)
+    }
+
+    #[test]
+    fn buckets_and_prefixes() {
+        assert_eq!(
+            parsed("ais://b"),
+            (bucket("b", Provider::Ais, None), String::new())
+        );
+        assert_eq!(
+            parsed("ais://b/p/"),
+            (bucket("b", Provider::Ais, None), "p".to_string())
+        );
+        assert_eq!(
+            parsed("ais://b/p/q"),
+            (bucket("b", Provider::Ais, None), "p/q".to_string())
+        );
+    }
+
+    #[test]
+    fn namespaces() {
+        assert_eq!(
+            parsed("ais://@uuid#ns/b/p"),
+            (
+                bucket("b", Provider::Ais, Some(("uuid", "ns"))),
+                "p".to_string()
+            )
+        );
+        assert_eq!(
+            parsed("ais://@uuid/b"),
+            (
+                bucket("b", Provider::Ais, Some(("uuid", ""))),
+                String::new()
+            )
+        );
+        // As printed by `url::Url`, which drops the `@`
+        assert_eq!(
+            parsed("ais://uuid#ns/b"),
+            (
+                bucket("b", Provider::Ais, Some(("uuid", "ns"))),
+                String::new()
+            )
+        );
+    }
+
+    #[test]
+    fn providers() {
+        assert_eq!(parsed("s3://b").0, bucket("b", Provider::Aws, None));
+        assert_eq!(parsed("aws://b").0, bucket("b", Provider::Aws, None));
+        assert_eq!(parsed("gs://b").0, bucket("b", Provider::Gcp, None));
+        assert_eq!(parsed("az://b").0, bucket("b", Provider::Azure, None));
+    }
+
+    #[test]
+    fn invalid_urls() {
+        for url in ["ais://", "ais:///p", "ais://@uuid", "ais://@uuid/", "b/p"] {
+            assert!(
+                matches!(parse_url(url), Err(BuilderError::InvalidUrl { .. })),
+                "{url}"
+            );
+        }
+        assert!(matches!(
+            parse_url("ftp://b"),
+            Err(BuilderError::InvalidProvider { .. })
+        ));
+    }
+
+    #[test]
+    fn qualified_buckets_require_the_native_api() {
+        let options = |api: &'static str| [("endpoint", "http://localhost:8080"), ("api", api)];
+
+        for url in ["ais://@remote#ns/b/p", "gs://b", "s3://b/p"] {
+            let error = parse_url_opts(url, options("s3")).unwrap_err();
+            assert!(error.to_string().contains("native API"), "{url}: {error}");
+
+            let (store, _) = parse_url_opts(url, options("native")).unwrap();
+            assert_eq!(store.bucket(), &parse_url(url).unwrap().bucket);
+        }
+
+        let (store, prefix) = parse_url_opts("ais://b/p", options("s3")).unwrap();
+        assert_eq!(store.bucket(), &bucket("b", Provider::Ais, None));
+        assert_eq!(prefix.as_ref(), "p");
+    }
+}
@@ -1,9 +1,13 @@
-use std::time::Duration;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
+use chrono::{DateTime, Utc};
 use futures::StreamExt;
 use object_store::PutPayload;
 use reqwest::{Body, Client, Method, Response, StatusCode};
+use url::Url;
 
+use crate::auth::TokenProvider;
 use crate::error::AiStoreError;
 
 /// Configuration for retry and redirect behavior
@@ -19,6 +23,82 @@ pub struct RequestPolicy {
     pub backoff_factor: f64,
     /// Maximum delay between retries
     pub max_retry_delay: Duration,
+    /// Sleep a random fraction of each backoff delay ("full jitter"), so that
+    /// clients failing together do not retry in lockstep
+    pub jitter: bool,
+    /// Total time after the first attempt within which retries may start
+    ///
+    /// A retry whose delay would end past this budget is not attempted.
+    pub retry_timeout: Duration,
+    /// Hosts that keep receiving the `Authorization` header when a redirect
+    /// leads away from the host requested
+    ///
+    /// Entries are host names, `*.{domain}` for a domain's subdomains, or `*`
+    /// for every host, on any port. Without an entry, only redirects within the
+    /// requested origin keep the header. AIStore proxies redirect to targets on
+    /// other hosts or ports, so clusters with authentication need their targets
+    /// listed here.
+    pub redirect_auth_hosts: Vec<String>,
+}
+
+impl RequestPolicy {
+    /// Calculate the next retry delay with exponential backoff
+    ///
+    /// A `backoff_factor` that yields no valid delay (NaN, negative or too
+    /// large) backs off to `max_retry_delay`.
+    pub(crate) fn next_retry_delay(&self, current: Duration) -> Duration {
+        Duration::try_from_secs_f64(current.as_secs_f64() * self.backoff_factor)
+            .map_or(self.max_retry_delay, |next| next.min(self.max_retry_delay))
+    }
+
+    /// The time to actually sleep for a backoff delay
+    pub(crate) fn jittered(&self, delay: Duration) -> Duration {
+        if self.jitter {
+            delay.mul_f64(rand::random::<f64>())
+        } else {
+            delay
+        }
+    }
+
+    /// Whether credentials may follow a redirect from `from` to `to`
+    ///
+    /// They may within the same origin (scheme, host and port) and to trusted
+    /// hosts; another port of the same host is another origin.
+    fn keeps_credentials(&self, from: Option<&Url>, to: &Url) -> bool {
+        let same_origin = from.is_some_and(|from| {
+            from.scheme() == to.scheme()
+                && from.host_str() == to.host_str()
+                && from.port_or_known_default() == to.port_or_known_default()
+        });
+
+        same_origin || self.trusts_redirect_host(to.host_str())
+    }
+
+    /// Whether credentials may follow a redirect to `host`, wherever it came from
+    fn trusts_redirect_host(&self, host: Option<&str>) -> bool {
+        let Some(host) = host else {
+            return false;
+        };
+
+        self.redirect_auth_hosts.iter().any(|allowed| {
+            allowed == "*"
+                || allowed.eq_ignore_ascii_case(host)
+                || allowed.strip_prefix("*.").is_some_and(|domain| {
+                    host.len() > domain.len()
+                        && host[..host.len() - domain.len()].ends_with('.')
+                        && host[host.len() - domain.len()..].eq_ignore_ascii_case(domain)
+                })
+        })
+    }
+
+    /// Whether a retry after `delay` still starts within the retry budget
+    pub(crate) fn within_budget(&self, started: Instant, delay: Duration) -> bool {
+        // A delay too long to add up, e.g. from `Retry-After`, is over any budget
+        started
+            .elapsed()
+            .checked_add(delay)
+            .is_some_and(|end| end <= self.retry_timeout)
+    }
 }
 
 impl Default for RequestPolicy {
@@ -29,21 +109,22 @@ impl Default for RequestPolicy {
             initial_retry_delay: Duration::from_millis(100),
             backoff_factor: 2.0,
             max_retry_delay: Duration::from_secs(10),
+            jitter: true,
+            retry_timeout: Duration::from_secs(180),
+            redirect_auth_hosts: Vec::new(),
         }
     }
 }
 
 #[derive(Debug, Clone)]
 pub enum RequestBody {
-    Bytes(bytes::Bytes),
     Payload(PutPayload),
     Text(String),
 }
 
-impl Into<Body> for RequestBody {
-    fn into(self) -> Body {
-        match self {
-            RequestBody::Bytes(bytes) => Body::from(bytes),
+impl From<RequestBody> for Body {
+    fn from(body: RequestBody) -> Self {
+        match body {
             RequestBody::Payload(payload) => {
                 let stream = futures::stream::iter(payload).map(Ok::<_, std::io::Error>);
                 Body::wrap_stream(stream)
@@ -53,27 +134,137 @@ impl Into<Body> for RequestBody {
     }
 }
 
+/// Rewrites the target of a redirect before it is followed, e.g. to replace the
+/// pod addresses of targets that are unreachable from outside the cluster
+#[derive(Clone)]
+pub(crate) struct RedirectRewrite(Arc<dyn Fn(Url) -> Url + Send + Sync>);
+
+impl RedirectRewrite {
+    pub(crate) fn new(rewrite: impl Fn(Url) -> Url + Send + Sync + 'static) -> Self {
+        Self(Arc::new(rewrite))
+    }
+
+    /// Replace the host of redirects whose `host:port` or `host` is a key of `map`
+    ///
+    /// Replacements are `host`, `host:port` or `scheme://host[:port]`. IPv6
+    /// addresses are written in brackets, in keys as in replacements.
+    pub(crate) fn from_host_map(map: Vec<(String, String)>) -> Self {
+        Self::new(move |mut url| {
+            let host = url.host_str().unwrap_or_default().to_string();
+            let host_port = url.port().map(|port| format!("{host}:{port}"));
+
+            let replacement = map
+                .iter()
+                .find(|(from, _)| Some(from) == host_port.as_ref())
+                .or_else(|| map.iter().find(|(from, _)| *from == host));
+
+            if let Some((_, to)) = replacement {
+                replace_host(&mut url, to);
+            }
+            url
+        })
+    }
+
+    fn apply(&self, url: Url) -> Url {
+        (self.0)(url)
+    }
+}
+
+impl std::fmt::Debug for RedirectRewrite {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("RedirectRewrite")
+    }
+}
+
+/// Point `url` at `to`, given as `host`, `host:port` or `scheme://host[:port]`
+///
+/// A bare `host` keeps the port of `url`; a scheme without a port means the
+/// scheme's default port. Leaves `url` unchanged if `to` is not a valid host,
+/// which includes IPv6 addresses without brackets.
+fn replace_host(url: &mut Url, to: &str) {
+    let mut rewritten = url.clone();
+
+    let ok = match to.split_once("://") {
+        Some(_) => Url::parse(to).is_ok_and(|to| {
+            rewritten.set_scheme(to.scheme()).is_ok()
+                && rewritten.set_host(to.host_str()).is_ok()
+                && rewritten.set_port(to.port()).is_ok()
+        }),
+        // Parsed as the authority of a URL whose scheme has no default port,
+        // so that an explicit port is always kept
+        None => Url::parse(&format!("authority://{to}")).is_ok_and(|to| {
+            to.path().is_empty()
+                && to.query().is_none()
+                && to.fragment().is_none()
+                && rewritten.set_host(to.host_str()).is_ok()
+                && rewritten.set_port(to.port().or(url.port())).is_ok()
+        }),
+    };
+
+    if ok {
+        *url = rewritten;
+    }
+}
+
+/// HTTP client shared by the API clients, carrying the credentials of the store
+#[derive(Debug, Clone)]
+pub(crate) struct HttpClient {
+    client: Client,
+    credentials: Option<Arc<dyn TokenProvider>>,
+    policy: RequestPolicy,
+    redirect_rewrite: Option<RedirectRewrite>,
+}
+
+impl HttpClient {
+    pub(crate) fn new(
+        client: Client,
+        credentials: Option<Arc<dyn TokenProvider>>,
+        policy: RequestPolicy,
+        redirect_rewrite: Option<RedirectRewrite>,
+    ) -> Self {
+        Self {
+            client,
+            credentials,
+            policy,
+            redirect_rewrite,
+        }
+    }
+
+    /// How requests of this client are retried
+    pub(crate) fn policy(&self) -> &RequestPolicy {
+        &self.policy
+    }
+}
+
 /// Builder for HTTP requests with retry and redirect handling
+#[derive(Clone)]
 pub struct HttpRequestBuilder {
     client: Client,
+    credentials: Option<Arc<dyn TokenProvider>>,
     method: Method,
     url: String,
     body: Option<RequestBody>,
     headers: Vec<(String, String)>,
     query_params: Vec<(String, String)>,
     policy: RequestPolicy,
+    /// Whether sending the request twice has the same effect as sending it once
+    idempotent: bool,
+    redirect_rewrite: Option<RedirectRewrite>,
 }
 
 impl HttpRequestBuilder {
-    pub fn new(client: Client, method: Method, url: impl Into<String>) -> Self {
+    pub(crate) fn new(client: HttpClient, method: Method, url: impl Into<String>) -> Self {
         Self {
-            client,
-            method,
+            client: client.client,
+            credentials: client.credentials,
+            method: method.clone(),
             url: url.into(),
             body: None,
             headers: Vec::new(),
             query_params: Vec::new(),
-            policy: RequestPolicy::default(),
+            policy: client.policy,
+            idempotent: method != Method::POST,
+            redirect_rewrite: client.redirect_rewrite,
         }
     }
 
@@ -101,27 +292,62 @@ impl HttpRequestBuilder {
         self
     }
 
-    /// Set the retry/redirect policy
-    pub fn policy(mut self, policy: RequestPolicy) -> Self {
-        self.policy = policy;
+    /// Mark the request as safe to repeat or not (default: all methods but POST)
+    ///
+    /// Requests that are not idempotent are only retried when the server
+    /// cannot have acted on them: connection failures, 429 and 503.
+    pub fn idempotent(mut self, idempotent: bool) -> Self {
+        self.idempotent = idempotent;
         self
     }
 
+    /// The retry/redirect policy this request is sent with
+    pub(crate) fn retry_policy(&self) -> &RequestPolicy {
+        &self.policy
+    }
+
     /// Send the request with retry and redirect handling
+    ///
+    /// Redirects are followed here rather than by reqwest: 307 and 308 keep the
+    /// method, body and credentials, 303 (and 301/302 after a POST) continue
+    /// with a GET without body, and credentials are dropped once a redirect
+    /// leaves the original origin for a host not in
+    /// [`RequestPolicy::redirect_auth_hosts`]. The configured redirect rewrite
+    /// is applied to every `Location` first. Retries start over with the
+    /// original request, not the last redirect target.
     pub async fn send(mut self) -> Result<Response, AiStoreError> {
+        let started = Instant::now();
+        let initial = self.clone();
+        let original = Url::parse(&self.url).ok();
+        let mut send_credentials = true;
         let mut redirects = 0;
         let mut retries = 0;
         let mut retry_delay = self.policy.initial_retry_delay;
+        let mut reauthenticated = false;
 
         loop {
-            let result = self.send_once().await;
+            let token = match &self.credentials {
+                Some(credentials) if send_credentials => Some(credentials.token().await?),
+                _ => None,
+            };
+
+            let result = self.send_once(token.as_deref()).await;
 
             match result {
                 Ok(response) => {
                     let status = response.status();
 
-                    // Handle redirects (301, 302, 307, 308)
-                    if status.is_redirection() {
+                    // The token may have expired or been revoked, retry once with a fresh one
+                    if status == StatusCode::UNAUTHORIZED && !reauthenticated {
+                        if let (Some(credentials), Some(token)) = (&self.credentials, &token) {
+                            credentials.invalidate(token).await;
+                            reauthenticated = true;
+                            continue;
+                        }
+                    }
+
+                    // Handle redirects (301, 302, 303, 307, 308)
+                    if Self::is_redirect(status) {
                         if redirects >= self.policy.max_redirects {
                             return Err(AiStoreError::InvalidResponse {
                                 message: format!(
@@ -131,38 +357,85 @@ impl HttpRequestBuilder {
                             });
                         }
 
-                        if let Some(location) = response.headers().get("location") {
-                            if let Ok(location_str) = location.to_str() {
-                                self.url = location_str.to_string();
-                                redirects += 1;
-                                continue;
-                            }
+                        let Some(location) = response
+                            .headers()
+                            .get(reqwest::header::LOCATION)
+                            .and_then(|l| l.to_str().ok())
+                        else {
+                            return Err(AiStoreError::InvalidResponse {
+                                message: format!(
+                                    "{} redirect without Location header",
+                                    status.as_u16()
+                                ),
+                            });
+                        };
+
+                        let next = Url::parse(&self.url)
+                            .and_then(|url| url.join(location))
+                            .map_err(|e| AiStoreError::InvalidResponse {
+                                message: format!("Invalid redirect location {}: {}", location, e),
+                            })?;
+                        let next = match &self.redirect_rewrite {
+                            Some(rewrite) => rewrite.apply(next),
+                            None => next,
+                        };
+
+                        let switch_to_get = status == StatusCode::SEE_OTHER
+                            || (matches!(
+                                status,
+                                StatusCode::MOVED_PERMANENTLY | StatusCode::FOUND
+                            ) && self.method == Method::POST);
+                        if switch_to_get && self.method != Method::HEAD {
+                            self.method = Method::GET;
+                            self.body = None;
+                            self.headers.retain(|(name, _)| {
+                                !name.to_ascii_lowercase().starts_with("content-")
+                            });
+                        }
+
+                        if !self.policy.keeps_credentials(original.as_ref(), &next) {
+                            send_credentials = false;
                         }
 
-                        return Err(AiStoreError::InvalidResponse {
-                            message: format!(
-                                "{} redirect without Location header",
-                                status.as_u16()
-                            ),
-                        });
+                        // The location already carries the complete query
+                        self.url = next.to_string();
+                        self.query_params.clear();
+                        redirects += 1;
+                        continue;
                     }
 
                     // Check for retryable status codes
-                    if Self::is_retryable_status(status) && retries < self.policy.max_retries {
-                        retries += 1;
-                        tokio::time::sleep(retry_delay).await;
-                        retry_delay = self.next_retry_delay(retry_delay);
-                        continue;
+                    if self.is_retryable_status(status) && retries < self.policy.max_retries {
+                        // Throttled and unavailable servers say when to come back
+                        let delay = retry_after(&response)
+                            .unwrap_or_else(|| self.policy.jittered(retry_delay));
+
+                        if self.policy.within_budget(started, delay) {
+                            retries += 1;
+                            tokio::time::sleep(delay).await;
+                            retry_delay = self.policy.next_retry_delay(retry_delay);
+                            self = initial.clone();
+                            send_credentials = true;
+                            redirects = 0;
+                            continue;
+                        }
                     }
 
                     return Ok(response);
                 }
                 Err(e) => {
                     // Retry on transient network errors
-                    if Self::is_retryable_error(&e) && retries < self.policy.max_retries {
+                    let delay = self.policy.jittered(retry_delay);
+                    if self.is_retryable_error(&e)
+                        && retries < self.policy.max_retries
+                        && self.policy.within_budget(started, delay)
+                    {
                         retries += 1;
-                        tokio::time::sleep(retry_delay).await;
-                        retry_delay = self.next_retry_delay(retry_delay);
+                        tokio::time::sleep(delay).await;
+                        retry_delay = self.policy.next_retry_delay(retry_delay);
+                        self = initial.clone();
+                        send_credentials = true;
+                        redirects = 0;
                         continue;
                     }
 
@@ -173,9 +446,13 @@ impl HttpRequestBuilder {
     }
 
     /// Send a single request without retry logic
-    async fn send_once(&mut self) -> Result<Response, AiStoreError> {
+    async fn send_once(&mut self, token: Option<&str>) -> Result<Response, AiStoreError> {
         let mut request = self.client.request(self.method.clone(), &self.url);
 
+        if let Some(token) = token {
+            request = request.bearer_auth(token);
+        }
+
         // Add query parameters
         if !self.query_params.is_empty() {
             request = request.query(&self.query_params);
@@ -186,8 +463,8 @@ impl HttpRequestBuilder {
             request = request.header(name.as_str(), value.as_str());
         }
 
-        // Add body if present (take ownership since Body is not Clone)
-        if let Some(body) = self.body.clone().take() {
+        // Add body if present (cloned so the request can be re-sent on retry)
+        if let Some(body) = self.body.clone() {
             request = request.body(body);
         }
 
@@ -197,14 +474,30 @@ impl HttpRequestBuilder {
             .map_err(|e| AiStoreError::Request { source: e })
     }
 
-    /// Calculate the next retry delay with exponential backoff
-    fn next_retry_delay(&self, current: Duration) -> Duration {
-        let next = Duration::from_secs_f64(current.as_secs_f64() * self.policy.backoff_factor);
-        next.min(self.policy.max_retry_delay)
+    /// Check if a status code is a redirect to follow (not 304 Not Modified)
+    fn is_redirect(status: StatusCode) -> bool {
+        matches!(
+            status,
+            StatusCode::MOVED_PERMANENTLY
+                | StatusCode::FOUND
+                | StatusCode::SEE_OTHER
+                | StatusCode::TEMPORARY_REDIRECT
+                | StatusCode::PERMANENT_REDIRECT
+        )
     }
 
     /// Check if a status code is retryable
-    fn is_retryable_status(status: StatusCode) -> bool {
+    ///
+    /// For requests that are not idempotent, only statuses that mean the
+    /// request was turned away before being processed.
+    fn is_retryable_status(&self, status: StatusCode) -> bool {
+        if !self.idempotent {
+            return matches!(
+                status,
+                StatusCode::TOO_MANY_REQUESTS | StatusCode::SERVICE_UNAVAILABLE
+            );
+        }
+
         matches!(
             status,
             StatusCode::REQUEST_TIMEOUT
@@ -217,8 +510,12 @@ impl HttpRequestBuilder {
     }
 
     /// Check if an error is retryable (transient network errors)
-    fn is_retryable_error(error: &AiStoreError) -> bool {
+    ///
+    /// A request that is not idempotent may have reached the server before a
+    /// timeout, so it is only retried if the connection was never established.
+    fn is_retryable_error(&self, error: &AiStoreError) -> bool {
         match error {
+            AiStoreError::Request { source } if !self.idempotent => source.is_connect(),
             AiStoreError::Request { source } => {
                 source.is_timeout() || source.is_connect() || source.is_request()
             }
@@ -227,6 +524,26 @@ impl HttpRequestBuilder {
     }
 }
 
+/// Delay requested by a `Retry-After` header, in seconds or as an HTTP date
+fn retry_after(response: &Response) -> Option<Duration> {
+    let value = response
+        .headers()
+        .get(reqwest::header::RETRY_AFTER)?
+        .to_str()
+        .ok()?;
+
+    if let Ok(seconds) = value.trim().parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+
+    let date = DateTime::parse_from_rfc2822(value).ok()?;
+    Some(
+        (date.with_timezone(&Utc) - Utc::now())
+            .to_std()
+            .unwrap_or_default(),
+    )
+}
+
 pub trait ClientExt {
     fn get_with_retry(&self, url: impl Into<String>) -> HttpRequestBuilder;
     fn put_with_retry(&self, url: impl Into<String>) -> HttpRequestBuilder;
@@ -235,7 +552,7 @@ pub trait ClientExt {
     fn head_with_retry(&self, url: impl Into<String>) -> HttpRequestBuilder;
 }
 
-impl ClientExt for Client {
+impl ClientExt for HttpClient {
     fn get_with_retry(&self, url: impl Into<String>) -> HttpRequestBuilder {
         HttpRequestBuilder::new(self.clone(), Method::GET, url)
     }
@@ -256,3 +573,455 @@ impl ClientExt for Client {
         HttpRequestBuilder::new(self.clone(), Method::HEAD, url)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::StaticTokenProvider;
+    use crate::mock::{MockServer, Reply};
+
+    const AUTHORIZATION: &str = "Bearer secret";
+
+    #[test]
+    fn invalid_backoff_factors_back_off_to_the_maximum() {
+        let second = Duration::from_secs(1);
+        for backoff_factor in [f64::NAN, -2.0, f64::INFINITY, f64::MAX] {
+            let policy = RequestPolicy {
+                backoff_factor,
+                ..Default::default()
+            };
+            assert_eq!(policy.next_retry_delay(second), policy.max_retry_delay);
+        }
+
+        let policy = RequestPolicy::default();
+        assert_eq!(policy.next_retry_delay(second), 2 * second);
+    }
+
+    #[test]
+    fn overlong_delays_are_over_budget() {
+        let policy = RequestPolicy::default();
+        let started = Instant::now();
+        assert!(policy.within_budget(started, Duration::from_secs(1)));
+        assert!(!policy.within_budget(started, Duration::from_secs(u64::MAX)));
+        assert!(!policy.within_budget(started, Duration::MAX));
+    }
+
+    fn client(policy: RequestPolicy) -> HttpClient {
+        let client = Client::builder()
+            .redirect(reqwest::redirect::Policy::none())
+            .build()
+            .unwrap();
+        let credentials = Arc::new(StaticTokenProvider::new("secret".to_string()));
+        HttpClient::new(client, Some(credentials), policy, None)
+    }
+
+    /// A target that accepts everything and a proxy that redirects every request
+    /// to it with `status`
+    async fn proxy_and_target(status: u16) -> (MockServer, MockServer) {
+        let mut target = MockServer::bind().await;
+        target.serve(|_| Reply::status(200));
+
+        let mut proxy = MockServer::bind().await;
+        let location = target.url().to_string();
+        proxy.serve(move |request| {
+            Reply::status(status).header("location", format!("{location}{}", request.target))
+        });
+
+        (proxy, target)
+    }
+
+    #[tokio::test]
+    async fn put_survives_method_preserving_redirects() {
+        for status in [307, 308] {
+            let (proxy, target) = proxy_and_target(status).await;
+
+            let response = client(RequestPolicy::default())
+                .put_with_retry(format!("{}/v1/objects/bucket/key", proxy.url()))
+                .body(RequestBody::Text("data".to_string()))
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+
+            let requests = target.requests();
+            assert_eq!(requests.len(), 1);
+            assert_eq!(requests[0].method, "PUT");
+            assert_eq!(requests[0].target, "/v1/objects/bucket/key");
+            assert_eq!(requests[0].body, b"data");
+        }
+    }
+
+    #[tokio::test]
+    async fn see_other_switches_to_get() {
+        let (proxy, target) = proxy_and_target(303).await;
+
+        client(RequestPolicy::default())
+            .put_with_retry(format!("{}/key", proxy.url()))
+            .header("content-type", "text/plain")
+            .body(RequestBody::Text("data".to_string()))
+            .send()
+            .await
+            .unwrap();
+
+        let requests = target.requests();
+        assert_eq!(requests[0].method, "GET");
+        assert!(requests[0].body.is_empty());
+        assert_eq!(requests[0].header("content-type"), None);
+    }
+
+    #[tokio::test]
+    async fn credentials_follow_same_origin_redirects() {
+        let mut server = MockServer::bind().await;
+        server.serve(|request| match request.target.as_str() {
+            "/start" => Reply::status(307).header("location", "/next"),
+            _ => Reply::status(200),
+        });
+
+        client(RequestPolicy::default())
+            .get_with_retry(format!("{}/start", server.url()))
+            .send()
+            .await
+            .unwrap();
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[1].target, "/next");
+        assert_eq!(requests[1].header("authorization"), Some(AUTHORIZATION));
+    }
+
+    #[tokio::test]
+    async fn credentials_are_dropped_across_ports() {
+        let (proxy, target) = proxy_and_target(307).await;
+
+        client(RequestPolicy::default())
+            .get_with_retry(format!("{}/key", proxy.url()))
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(
+            proxy.requests()[0].header("authorization"),
+            Some(AUTHORIZATION)
+        );
+        assert_eq!(target.requests()[0].header("authorization"), None);
+    }
+
+    #[tokio::test]
+    async fn retries_start_over_at_the_original_url() {
+        let mut target = MockServer::bind().await;
+        let failed = std::sync::atomic::AtomicBool::new(false);
+        target.serve(move |_| {
+            if failed.swap(true, std::sync::atomic::Ordering::SeqCst) {
+                Reply::status(200)
+            } else {
+                Reply::status(500)
+            }
+        });
+
+        let mut proxy = MockServer::bind().await;
+        let location = target.url().to_string();
+        proxy.serve(move |request| match request.target.as_str() {
+            "/key?provider=ais" => {
+                Reply::status(303).header("location", format!("{location}/target"))
+            }
+            _ => Reply::status(400),
+        });
+        let policy = RequestPolicy {
+            initial_retry_delay: Duration::from_millis(1),
+            ..Default::default()
+        };
+
+        let response = client(policy)
+            .put_with_retry(format!("{}/key", proxy.url()))
+            .query("provider", "ais")
+            .body(RequestBody::Text("data".to_string()))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let requests = proxy.requests();
+        assert_eq!(requests.len(), 2);
+        for request in &requests {
+            assert_eq!(request.method, "PUT");
+            assert_eq!(request.body, b"data");
+            assert_eq!(request.header("authorization"), Some(AUTHORIZATION));
+        }
+        let requests = target.requests();
+        assert_eq!(requests.len(), 2);
+        assert!(requests.iter().all(|request| request.method == "GET"));
+    }
+
+    #[tokio::test]
+    async fn credentials_are_dropped_across_hosts() {
+        let (proxy, target) = proxy_and_target(307).await;
+
+        // The proxy is asked as `localhost` and redirects to `127.0.0.1`
+        client(RequestPolicy::default())
+            .get_with_retry(format!(
+                "{}/key",
+                proxy.url().replace("127.0.0.1", "localhost")
+            ))
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(
+            proxy.requests()[0].header("authorization"),
+            Some(AUTHORIZATION)
+        );
+        assert_eq!(target.requests()[0].header("authorization"), None);
+    }
+
+    #[tokio::test]
+    async fn credentials_follow_redirects_to_trusted_hosts() {
+        let (proxy, target) = proxy_and_target(307).await;
+        let policy = RequestPolicy {
+            redirect_auth_hosts: vec!["127.0.0.1".to_string()],
+            ..Default::default()
+        };
+
+        client(policy)
+            .get_with_retry(format!(
+                "{}/key",
+                proxy.url().replace("127.0.0.1", "localhost")
+            ))
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(
+            target.requests()[0].header("authorization"),
+            Some(AUTHORIZATION)
+        );
+    }
+
+    #[tokio::test]
+    async fn redirects_are_limited() {
+        let mut server = MockServer::bind().await;
+        server.serve(|_| Reply::status(307).header("location", "/again"));
+        let policy = RequestPolicy {
+            max_redirects: 2,
+            ..Default::default()
+        };
+
+        let err = client(policy)
+            .get_with_retry(format!("{}/key", server.url()))
+            .send()
+            .await
+            .unwrap_err();
+
+        assert!(matches!(err, AiStoreError::InvalidResponse { .. }), "{err}");
+        assert_eq!(server.requests().len(), 3);
+    }
+
+    /// A server that answers the first request with 503 and `Retry-After: value`,
+    /// and every later one with 200
+    async fn retry_after_server(value: String) -> MockServer {
+        let mut server = MockServer::bind().await;
+        let first = std::sync::atomic::AtomicBool::new(true);
+        server.serve(move |_| {
+            if first.swap(false, std::sync::atomic::Ordering::SeqCst) {
+                Reply::status(503).header("retry-after", value.clone())
+            } else {
+                Reply::status(200)
+            }
+        });
+        server
+    }
+
+    /// How long sending a GET to `server` takes with a backoff of 1ms
+    async fn time_to_success(server: &MockServer) -> Duration {
+        let policy = RequestPolicy {
+            initial_retry_delay: Duration::from_millis(1),
+            jitter: false,
+            ..Default::default()
+        };
+        let started = Instant::now();
+        let response = client(policy)
+            .get_with_retry(format!("{}/key", server.url()))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(server.requests().len(), 2);
+        started.elapsed()
+    }
+
+    #[tokio::test]
+    async fn retry_after_in_seconds_is_honored() {
+        let server = retry_after_server("1".to_string()).await;
+        assert!(time_to_success(&server).await >= Duration::from_secs(1));
+    }
+
+    #[tokio::test]
+    async fn retry_after_as_a_date_is_honored() {
+        // HTTP dates have whole seconds, so the delay is between 1 and 2 seconds
+        let date = Utc::now() + chrono::Duration::seconds(2);
+        let server = retry_after_server(date.format("%a, %d %b %Y %H:%M:%S GMT").to_string()).await;
+        assert!(time_to_success(&server).await >= Duration::from_secs(1));
+    }
+
+    #[tokio::test]
+    async fn jittered_retries_wait_at_most_the_backoff() {
+        let arrivals = Arc::new(std::sync::Mutex::new(vec![]));
+        let mut server = MockServer::bind().await;
+        let recorded = Arc::clone(&arrivals);
+        server.serve(move |_| {
+            recorded.lock().unwrap().push(Instant::now());
+            Reply::status(500)
+        });
+        let backoff = Duration::from_millis(100);
+        let policy = RequestPolicy {
+            max_retries: 10,
+            initial_retry_delay: backoff,
+            backoff_factor: 1.0,
+            ..Default::default()
+        };
+
+        let response = client(policy)
+            .get_with_retry(format!("{}/key", server.url()))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+
+        let arrivals = arrivals.lock().unwrap();
+        assert_eq!(arrivals.len(), 11);
+        let gaps: Vec<_> = arrivals.windows(2).map(|w| w[1] - w[0]).collect();
+        // Allow some time for the request itself on top of the delay
+        assert!(
+            gaps.iter()
+                .all(|gap| *gap < backoff + Duration::from_millis(50)),
+            "{gaps:?}"
+        );
+        // Without jitter, the retries would take ten full delays
+        assert!(gaps.iter().sum::<Duration>() < 10 * backoff, "{gaps:?}");
+    }
+
+    #[tokio::test]
+    async fn retries_stop_at_the_retry_timeout() {
+        let mut server = MockServer::bind().await;
+        server.serve(|request| match request.target.as_str() {
+            "/throttled" => Reply::status(503).header("retry-after", "3600"),
+            _ => Reply::status(500),
+        });
+        let policy = RequestPolicy {
+            max_retries: 100,
+            initial_retry_delay: Duration::from_millis(100),
+            backoff_factor: 1.0,
+            jitter: false,
+            retry_timeout: Duration::from_millis(250),
+            ..Default::default()
+        };
+        let client = client(policy);
+
+        // Retries start at 100ms and 200ms, one at 300ms would be past the timeout
+        let response = client
+            .get_with_retry(format!("{}/failing", server.url()))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(server.requests().len(), 3);
+
+        // A Retry-After past the timeout is not waited for
+        let started = Instant::now();
+        let response = client
+            .get_with_retry(format!("{}/throttled", server.url()))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(server.requests().len(), 4);
+        assert!(started.elapsed() < Duration::from_secs(1));
+    }
+
+    #[test]
+    fn host_map_rewrites_redirect_targets() {
+        let rewrite = RedirectRewrite::from_host_map(vec![
+            ("10.0.0.1:8081".to_string(), "gw.example:9000".to_string()),
+            ("10.0.0.1".to_string(), "gw.example".to_string()),
+            ("10.0.0.2".to_string(), "https://secure.example".to_string()),
+        ]);
+        let apply = |url: &str| rewrite.apply(Url::parse(url).unwrap()).to_string();
+
+        assert_eq!(
+            apply("http://10.0.0.1:8081/v1/objects/b/k?provider=ais"),
+            "http://gw.example:9000/v1/objects/b/k?provider=ais"
+        );
+        // A bare host keeps the port
+        assert_eq!(apply("http://10.0.0.1:8082/k"), "http://gw.example:8082/k");
+        assert_eq!(apply("http://10.0.0.2:8081/k"), "https://secure.example/k");
+        assert_eq!(apply("http://10.0.0.3:8081/k"), "http://10.0.0.3:8081/k");
+    }
+
+    #[test]
+    fn host_map_handles_ipv6_in_brackets() {
+        let rewrite = RedirectRewrite::from_host_map(vec![
+            ("[fd00::1]:8081".to_string(), "gw.example:9000".to_string()),
+            ("10.0.0.1".to_string(), "[fd00::2]:9000".to_string()),
+            ("10.0.0.2".to_string(), "[fd00::3]".to_string()),
+            ("10.0.0.3".to_string(), "https://[fd00::4]".to_string()),
+            ("10.0.0.4".to_string(), "fd00::5".to_string()),
+            ("10.0.0.5".to_string(), "gw.example:9000/path".to_string()),
+        ]);
+        let apply = |url: &str| rewrite.apply(Url::parse(url).unwrap()).to_string();
+
+        assert_eq!(apply("http://[fd00::1]:8081/k"), "http://gw.example:9000/k");
+        assert_eq!(apply("http://10.0.0.1:8081/k"), "http://[fd00::2]:9000/k");
+        assert_eq!(apply("http://10.0.0.2:8081/k"), "http://[fd00::3]:8081/k");
+        assert_eq!(apply("http://10.0.0.3:8081/k"), "https://[fd00::4]/k");
+        // Without brackets, the value is not a host
+        assert_eq!(apply("http://10.0.0.4:8081/k"), "http://10.0.0.4:8081/k");
+        assert_eq!(apply("http://10.0.0.5:8081/k"), "http://10.0.0.5:8081/k");
+    }
+
+    #[tokio::test]
+    async fn redirects_are_rewritten_before_they_are_followed() {
+        let mut target = MockServer::bind().await;
+        target.serve(|_| Reply::status(200));
+        let port = Url::parse(target.url()).unwrap().port().unwrap();
+
+        let mut proxy = MockServer::bind().await;
+        proxy.serve(move |_| {
+            Reply::status(307).header("location", format!("http://target.invalid:{port}/key"))
+        });
+
+        let mut client = client(RequestPolicy::default());
+        client.redirect_rewrite = Some(RedirectRewrite::from_host_map(vec![(
+            "target.invalid".to_string(),
+            "127.0.0.1".to_string(),
+        )]));
+
+        client
+            .get_with_retry(format!("{}/key", proxy.url()))
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(target.requests()[0].target, "/key");
+    }
+
+    #[test]
+    fn trusted_redirect_hosts() {
+        let policy = RequestPolicy {
+            redirect_auth_hosts: vec!["target.example".to_string(), "*.ais.internal".to_string()],
+            ..Default::default()
+        };
+
+        assert!(policy.trusts_redirect_host(Some("TARGET.example")));
+        assert!(policy.trusts_redirect_host(Some("t1.ais.internal")));
+        assert!(policy.trusts_redirect_host(Some("a.t1.ais.internal")));
+        assert!(!policy.trusts_redirect_host(Some("ais.internal")));
+        assert!(!policy.trusts_redirect_host(Some("evilais.internal")));
+        assert!(!policy.trusts_redirect_host(Some("other.example")));
+        assert!(!policy.trusts_redirect_host(None));
+
+        let policy = RequestPolicy {
+            redirect_auth_hosts: vec!["*".to_string()],
+            ..Default::default()
+        };
+        assert!(policy.trusts_redirect_host(Some("anywhere.example")));
+    }
+}
@@ -72,6 +72,67 @@ impl CompleteMultipartUploadRequest {
     }
 }
 
+/// Request body for DeleteObjects
+#[derive(Debug, Serialize)]
+#[serde(rename = "Delete", rename_all = "PascalCase")]
+pub struct DeleteObjectsRequest {
+    #[serde(rename = "Object")]
+    pub objects: Vec<ObjectIdentifier>,
+    pub quiet: bool,
+}
+
+/// An object in DeleteObjects request
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct ObjectIdentifier {
+    pub key: String,
+}
+
+impl DeleteObjectsRequest {
+    /// Build a quiet request, whose response only lists the keys that failed
+    pub fn new(keys: impl IntoIterator<Item = String>) -> Self {
+        Self {
+            objects: keys
+                .into_iter()
+                .map(|key| ObjectIdentifier { key })
+                .collect(),
+            quiet: true,
+        }
+    }
+}
+
+/// Response from DeleteObjects
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct DeleteResult {
+    #[serde(rename = "Error", default)]
+    pub errors: Vec<DeleteError>,
+}
+
+/// A key that could not be deleted in DeleteObjects response
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct DeleteError {
+    pub key: String,
+    #[serde(default)]
+    pub code: String,
+    #[serde(default)]
+    pub message: String,
+}
+
+/// Error body of a failed S3 request
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct ErrorResponse {
+    pub code: String,
+    #[serde(default)]
+    pub message: Option<String>,
+    #[serde(default)]
+    pub resource: Option<String>,
+    #[serde(default)]
+    pub request_id: Option<String>,
+}
+
 /// Parse XML response using quick-xml
 pub fn from_xml<'de, T: Deserialize<'de>>(xml: &'de str) -> Result<T, quick_xml::DeError> {
     quick_xml::de::from_str(xml)